@@ -0,0 +1,35 @@
+use std::{env, process::Command};
+
+/// Records how the benchmark was built, for the run metadata.
+fn main() {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = output(Command::new(rustc).arg("--version")).unwrap_or_default();
+    println!("cargo:rustc-env=BENCH_RUSTC_VERSION={rustc_version}");
+
+    let target_features = env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+    println!("cargo:rustc-env=BENCH_TARGET_FEATURES={target_features}");
+    let rustflags = env::var("CARGO_ENCODED_RUSTFLAGS").unwrap_or_default().replace('\x1f', " ");
+    println!("cargo:rustc-env=BENCH_RUSTFLAGS={rustflags}");
+    println!("cargo:rustc-env=BENCH_PROFILE={}", env::var("PROFILE").unwrap_or_default());
+
+    let commit = output(Command::new("git").args(["rev-parse", "HEAD"])).unwrap_or_default();
+    let dirty = output(Command::new("git").args(["status", "--porcelain", "--untracked-files=no"]))
+        .is_some_and(|status| !status.is_empty());
+    println!("cargo:rustc-env=BENCH_GIT_COMMIT={commit}");
+    println!("cargo:rustc-env=BENCH_GIT_DIRTY={dirty}");
+
+    // Rebuild when the checked out commit or the sources change, so the dirty flag is current.
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=../src");
+    let git_dir = output(Command::new("git").args(["rev-parse", "--absolute-git-dir"]));
+    if let Some(git_dir) = git_dir {
+        println!("cargo:rerun-if-changed={git_dir}/HEAD");
+        println!("cargo:rerun-if-changed={git_dir}/index");
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+fn output(command: &mut Command) -> Option<String> {
+    let output = command.output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
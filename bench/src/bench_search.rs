@@ -0,0 +1,232 @@
+use std::{any::type_name, hint::black_box, io, thread, time::Instant};
+
+use binary_search::{QueryKind, SearchScheme, Searchable};
+
+use crate::{
+    cache::{CacheControl, CacheMode},
+    experiment::Measurement,
+    latency::{self, Bucket},
+};
+
+/// Where `run_exps` puts its results.
+pub trait Sink {
+    /// The results so far, including any from an earlier attempt at the same run.
+    fn results(&self) -> &[QueryResult];
+    /// Adds a result, with the histogram of its sampled latencies if there are any.
+    fn push(&mut self, result: QueryResult, histogram: Vec<Bucket>) -> io::Result<()>;
+}
+
+/// Measures every scheme of `I` that `m` selects, with every number of threads and in every
+/// cache mode, until `sink` has `m.repetitions` results for it, and pushes each result as
+/// soon as it is done. The schemes take turns, so a slow drift in the machine affects them
+/// alike. `I` is only built if a scheme needs measuring.
+pub fn run_exps<I: Searchable + Sync + 'static, K: QueryKind>(sink: &mut impl Sink, m: &Measurement) -> io::Result<()> {
+    let funcs = if m.schemes.is_empty() {
+        I::get_funcs::<K>()
+    } else {
+        I::all_funcs::<K>().into_iter()
+            .filter(|func| m.schemes.iter().any(|pattern| short_name(&func.get_name()).contains(pattern.as_str())))
+            .collect()
+    };
+    let pending: Vec<_> = funcs.into_iter()
+        .flat_map(|func| m.threads.iter().map(move |&threads| (func, threads)))
+        .flat_map(|(func, threads)| m.caches.iter().map(move |cache| (func, threads, cache)))
+        .map(|(func, threads, cache)| {
+            let name = func.get_name();
+            let done = sink.results().iter().filter(|r| r.is_of::<I, K>(&name, m.size, threads, cache.mode())).count();
+            (func, threads, cache, done)
+        })
+        .filter(|&(_, _, _, done)| done < m.repetitions)
+        .collect();
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let searchable = I::new(m.vals);
+    for repetition in 0..m.repetitions {
+        for &(func, threads, cache, done) in &pending {
+            if repetition >= done {
+                // A batched scheme would answer each sampled query with a whole batch.
+                let samples = if threads == 1 && func.batch_size() == 1 { m.latency_samples } else { 0 };
+                let (query_result, histogram) = QueryResult::new(&searchable, m.queries, func, K::get_name(), m.size, threads, cache, samples);
+                sink.push(query_result, histogram)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `name` without the module paths, so `binary_search::searches::s_tree::STree::batch<128,
+/// binary_search::query::query_kind::LowerBound>` becomes `STree::batch<128, LowerBound>`.
+pub fn short_name(name: &str) -> String {
+    let mut short = String::new();
+    let mut rest = name;
+    while let Some(i) = rest.find("::") {
+        short.push_str(&rest[..i]);
+        let start = short.rfind(|c: char| !(c.is_alphanumeric() || c == '_')).map_or(0, |j| j + 1);
+        if short[start..].starts_with(|c: char| c.is_lowercase()) {
+            short.truncate(start);
+        } else {
+            short.push_str("::");
+        }
+        rest = &rest[i + 2..];
+    }
+    short.push_str(rest);
+    short
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct QueryResult{
+    pub searchable_name: String,
+    pub scheme_name: String,
+    pub query_kind: String,
+    // Input size in bytes
+    pub size: usize,
+    // Time for all queries
+    pub duration_ns: u64,
+    // Latency, or inverse throughput, per operation
+    pub latency: f64,
+    // Threads the queries were split between
+    #[serde(default = "one_thread")]
+    pub threads: usize,
+    // Percentiles of the latency of single queries, where those were sampled
+    #[serde(default)]
+    pub p50_ns: Option<f64>,
+    #[serde(default)]
+    pub p90_ns: Option<f64>,
+    #[serde(default)]
+    pub p99_ns: Option<f64>,
+    #[serde(default)]
+    pub p999_ns: Option<f64>,
+    // State of the caches during the measurement
+    #[serde(default)]
+    pub cache_mode: CacheMode,
+}
+
+fn one_thread() -> usize {
+    1
+}
+
+impl QueryResult{
+    /// Whether this measured `scheme` answering `K` on an `I` of `size` bytes with `threads`
+    /// threads in `cache_mode`. The searchable name is the type name of `I`, followed by details of the
+    /// instance for some searchables.
+    pub fn is_of<I: Searchable, K: QueryKind>(&self, scheme: &str, size: usize, threads: usize, cache_mode: CacheMode) -> bool {
+        let details = self.searchable_name.strip_prefix(type_name::<I>());
+        self.size == size
+            && self.threads == threads
+            && self.cache_mode == cache_mode
+            && self.scheme_name == scheme
+            && self.query_kind == K::get_name()
+            && details.is_some_and(|details| details.is_empty() || details.starts_with(" ("))
+    }
+
+    /// Times all queries, split between `threads` threads, then the latency of `samples` of
+    /// them one at a time, and returns the histogram of the latter. `cache` sets up the
+    /// caches before each of the two.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<I: Searchable + Sync>(
+        searchable: &I,
+        queries: &[u32],
+        scheme: &dyn SearchScheme<I>,
+        query_kind: String,
+        size: usize,
+        threads: usize,
+        cache: &CacheControl,
+        samples: usize,
+    ) -> (Self, Vec<Bucket>)
+    {
+        let warm_up = |queries| {
+            black_box(scheme.query(searchable, queries));
+        };
+        let duration = cache.run(|| warm_up(queries), || {
+            let now = Instant::now();
+            if threads == 1 {
+                black_box(scheme.query(searchable, queries));
+            } else {
+                thread::scope(|s| {
+                    for chunk in queries.chunks(queries.len().div_ceil(threads)) {
+                        s.spawn(move || black_box(scheme.query(searchable, chunk)));
+                    }
+                });
+            }
+            now.elapsed()
+        });
+        let latency = duration.as_nanos() as f64 / queries.len() as f64;
+
+        let sz = size::Size::from_bytes(size);
+        let sz = format!("{}", sz);
+
+        println!("Query size: {sz:>8}");
+
+        let sampled = if samples == 0 {
+            vec!()
+        } else {
+            let sampled_queries = &queries[..samples.min(queries.len())];
+            cache.run(|| warm_up(sampled_queries), || latency::sample(searchable, scheme, sampled_queries, samples))
+        };
+        let percentile = |p| (!sampled.is_empty()).then(|| latency::percentile(&sampled, p));
+
+        let result = QueryResult{
+            duration_ns: duration.as_nanos() as u64,
+            searchable_name: searchable.get_name(),
+            size,
+            latency,
+            scheme_name: scheme.get_name(),
+            query_kind,
+            threads,
+            p50_ns: percentile(0.5),
+            p90_ns: percentile(0.9),
+            p99_ns: percentile(0.99),
+            p999_ns: percentile(0.999),
+            cache_mode: cache.mode(),
+        };
+        (result, latency::histogram(&sampled))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use binary_search::{LowerBound, VebTree};
+
+    use super::*;
+
+    struct Collect(Vec<QueryResult>);
+
+    impl Sink for Collect {
+        fn results(&self) -> &[QueryResult] {
+            &self.0
+        }
+
+        fn push(&mut self, result: QueryResult, histogram: Vec<Bucket>) -> io::Result<()> {
+            assert_eq!(histogram.is_empty(), result.p50_ns.is_none());
+            self.0.push(result);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_latency_samples_skip_batches() {
+        let vals: Vec<u32> = (0..1000).map(|i| 3 * i).collect();
+        let queries: Vec<u32> = (0..512).map(|i| 7 * i).collect();
+        let caches = [CacheControl::new(CacheMode::Warm, 1 << 20).unwrap()];
+        let m = Measurement {
+            vals: &vals,
+            queries: &queries,
+            size: size_of_val(&vals[..]),
+            schemes: &[],
+            repetitions: 1,
+            threads: &[1, 2],
+            latency_samples: 100,
+            caches: &caches,
+        };
+        let mut sink = Collect(vec!());
+        run_exps::<VebTree, LowerBound>(&mut sink, &m).unwrap();
+
+        // VebTree has schemes with and without batches.
+        assert!(sink.0.iter().any(|r| r.p50_ns.is_some()));
+        for result in &sink.0 {
+            let sampled = result.threads == 1 && !result.scheme_name.contains("Batched<");
+            assert_eq!(result.p50_ns.is_some(), sampled, "{} with {} threads", result.scheme_name, result.threads);
+        }
+    }
+}
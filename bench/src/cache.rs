@@ -0,0 +1,183 @@
+use std::{
+    fs,
+    hint::black_box,
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+/// The state of the caches while a scheme is measured.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheMode {
+    /// The queries are answered once, untimed, before each pass, so whatever they touch of
+    /// the structure is cached as far as it fits.
+    #[default]
+    Warm,
+    /// A buffer of twice the last level cache is read before each pass, so the pass starts
+    /// with none of the structure cached. The fewer the queries, the more of them find the
+    /// caches cold.
+    Cold,
+    /// Warm, but another thread keeps reading a buffer the size of the last level cache
+    /// during the pass, so the structure only gets what that leaves of it. That thread is
+    /// pinned to a physical core of its own and the pass to the others, so this mode needs
+    /// at least two.
+    LlcLimited,
+}
+
+impl CacheMode {
+    pub const ALL: [CacheMode; 3] = [CacheMode::Warm, CacheMode::Cold, CacheMode::LlcLimited];
+
+    /// The name the mode has in experiments and results.
+    pub fn name(self) -> &'static str {
+        match self {
+            CacheMode::Warm => "warm",
+            CacheMode::Cold => "cold",
+            CacheMode::LlcLimited => "llc-limited",
+        }
+    }
+}
+
+/// Puts the caches into a `CacheMode` before each pass of a measurement.
+pub struct CacheControl {
+    mode: CacheMode,
+    /// What is read to evict the structure. Filled with ones, since pages of zeros can all
+    /// be mapped to the same frame until they are written.
+    buffer: Vec<u64>,
+    /// For `LlcLimited`, the CPUs of the thread reading `buffer` and the CPUs of the pass,
+    /// which share no physical core.
+    pinning: Option<(libc::cpu_set_t, libc::cpu_set_t)>,
+}
+
+impl CacheControl {
+    /// Fails for `LlcLimited` if the process can't use two physical cores.
+    pub fn new(mode: CacheMode, llc_size: usize) -> io::Result<Self> {
+        let size = match mode {
+            CacheMode::Warm => 0,
+            CacheMode::Cold => 2 * llc_size,
+            CacheMode::LlcLimited => llc_size,
+        };
+        let pinning = match mode {
+            CacheMode::LlcLimited => Some(split_cores()?),
+            _ => None,
+        };
+        Ok(CacheControl { mode, buffer: vec![1; size / size_of::<u64>()], pinning })
+    }
+
+    pub fn mode(&self) -> CacheMode {
+        self.mode
+    }
+
+    /// Runs `pass` in the state of the mode, calling `warm_up` first in the modes that
+    /// start warm.
+    pub fn run<T>(&self, warm_up: impl FnOnce(), pass: impl FnOnce() -> T) -> T {
+        match self.mode {
+            CacheMode::Warm => {
+                warm_up();
+                pass()
+            }
+            CacheMode::Cold => {
+                self.thrash();
+                pass()
+            }
+            CacheMode::LlcLimited => {
+                let (thrasher, measure) = self.pinning.as_ref().unwrap();
+                // The threads of a pass inherit the CPUs of this one.
+                let previous = affinity().expect("failed to read the CPU affinity");
+                set_affinity(measure).expect("failed to pin the measurement");
+                warm_up();
+                let stop = AtomicBool::new(false);
+                let result = thread::scope(|s| {
+                    s.spawn(|| {
+                        set_affinity(thrasher).expect("failed to pin the thread that fills the cache");
+                        while !stop.load(Ordering::Relaxed) {
+                            self.thrash();
+                        }
+                    });
+                    let result = pass();
+                    stop.store(true, Ordering::Relaxed);
+                    result
+                });
+                set_affinity(&previous).expect("failed to restore the CPU affinity");
+                result
+            }
+        }
+    }
+
+    /// Reads a word from every cache line of the buffer.
+    fn thrash(&self) {
+        let words_per_line = 64 / size_of::<u64>();
+        black_box(self.buffer.iter().step_by(words_per_line).fold(0, |acc, &word| acc ^ word));
+    }
+}
+
+/// The CPUs the calling thread may run on.
+fn affinity() -> io::Result<libc::cpu_set_t> {
+    // SAFETY: an all zero `cpu_set_t` is the empty set, and the kernel writes at most its size.
+    let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+    match unsafe { libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut set) } {
+        0 => Ok(set),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Restricts the calling thread to `set`.
+fn set_affinity(set: &libc::cpu_set_t) -> io::Result<()> {
+    // SAFETY: `set` is a valid `cpu_set_t` of the size given.
+    match unsafe { libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), set) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Splits the CPUs the process may use into the hyperthreads of one physical core, and
+/// all the others.
+fn split_cores() -> io::Result<(libc::cpu_set_t, libc::cpu_set_t)> {
+    let allowed = affinity()?;
+    let mut cores: Vec<(String, Vec<usize>)> = vec!();
+    // SAFETY: every index is below `CPU_SETSIZE`, the number of CPUs a set holds.
+    for cpu in (0..libc::CPU_SETSIZE as usize).filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &allowed) }) {
+        let topology = |file: &str| fs::read_to_string(format!("/sys/devices/system/cpu/cpu{cpu}/topology/{file}"));
+        let core = format!("{}/{}", topology("physical_package_id")?.trim(), topology("core_id")?.trim());
+        match cores.iter_mut().find(|(id, _)| *id == core) {
+            Some((_, cpus)) => cpus.push(cpu),
+            None => cores.push((core, vec!(cpu))),
+        }
+    }
+    if cores.len() < 2 {
+        let message = format!("the llc-limited cache mode needs two physical cores, but only {} is available", cores.len());
+        return Err(io::Error::other(message));
+    }
+    let set = |cpus: &mut dyn Iterator<Item = &usize>| {
+        // SAFETY: an all zero `cpu_set_t` is the empty set.
+        let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+        // SAFETY: the CPUs came from a set, so they are below `CPU_SETSIZE`.
+        cpus.for_each(|&cpu| unsafe { libc::CPU_SET(cpu, &mut set) });
+        set
+    };
+    let (_, thrasher) = cores.pop().unwrap();
+    Ok((set(&mut thrasher.iter()), set(&mut cores.iter().flat_map(|(_, cpus)| cpus))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_cores() {
+        let allowed = affinity().unwrap();
+        let is_set = |cpu, set| unsafe { libc::CPU_ISSET(cpu, set) };
+        match split_cores() {
+            Ok((thrasher, measure)) => {
+                for cpu in 0..libc::CPU_SETSIZE as usize {
+                    assert!(!(is_set(cpu, &thrasher) && is_set(cpu, &measure)), "cpu {cpu} is in both");
+                    assert_eq!(is_set(cpu, &allowed), is_set(cpu, &thrasher) || is_set(cpu, &measure), "cpu {cpu}");
+                }
+                assert!((0..libc::CPU_SETSIZE as usize).any(|cpu| is_set(cpu, &thrasher)));
+                assert!((0..libc::CPU_SETSIZE as usize).any(|cpu| is_set(cpu, &measure)));
+            }
+            // Only where the process is limited to one physical core.
+            Err(err) => assert!(err.to_string().contains("two physical cores"), "{err}"),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+
+use crate::{bench_search::QueryResult, cache::CacheMode, stats::{welch, Summary}};
+
+/// The same measurement in two runs.
+pub struct Comparison {
+    pub query_kind: String,
+    pub searchable_name: String,
+    pub scheme_name: String,
+    pub size: usize,
+    pub threads: usize,
+    pub cache_mode: CacheMode,
+    pub baseline: Summary,
+    pub candidate: Summary,
+    /// The change in mean latency, relative to the baseline: 0.1 is 10% slower.
+    pub change: f64,
+    /// The p-value of the change, `None` unless both runs measured it at least twice.
+    pub p: Option<f64>,
+}
+
+impl Comparison {
+    /// Whether this is significantly slower by more than `threshold`, as a fraction of the
+    /// baseline. Without repetitions there is no significance, and only the threshold counts.
+    pub fn is_regression(&self, threshold: f64, alpha: f64) -> bool {
+        self.change > threshold && self.p.is_none_or(|p| p < alpha)
+    }
+
+    pub fn is_improvement(&self, threshold: f64, alpha: f64) -> bool {
+        self.change < -threshold && self.p.is_none_or(|p| p < alpha)
+    }
+}
+
+/// The latencies of each measurement, keyed by query kind, searchable, scheme, size, number
+/// of threads and cache mode.
+type Measurements<'a> = BTreeMap<(&'a str, &'a str, &'a str, usize, usize, CacheMode), Vec<f64>>;
+
+fn measurements(results: &[QueryResult]) -> Measurements<'_> {
+    let mut measurements = Measurements::new();
+    for r in results {
+        let key = (r.query_kind.as_str(), r.searchable_name.as_str(), r.scheme_name.as_str(), r.size, r.threads, r.cache_mode);
+        measurements.entry(key).or_default().push(r.latency);
+    }
+    measurements
+}
+
+/// Compares every measurement that both runs made, and counts the ones only one of them
+/// made.
+pub fn compare(baseline: &[QueryResult], candidate: &[QueryResult]) -> (Vec<Comparison>, usize) {
+    let (baseline, mut candidate) = (measurements(baseline), measurements(candidate));
+    let mut unmatched = 0;
+    let mut comparisons = Vec::new();
+    for (key, latencies) in baseline {
+        let Some(candidate_latencies) = candidate.remove(&key) else {
+            unmatched += 1;
+            continue;
+        };
+        let (query_kind, searchable_name, scheme_name, size, threads, cache_mode) = key;
+        let (baseline, candidate) = (Summary::new(&latencies), Summary::new(&candidate_latencies));
+        comparisons.push(Comparison {
+            query_kind: query_kind.to_string(),
+            searchable_name: searchable_name.to_string(),
+            scheme_name: scheme_name.to_string(),
+            size,
+            threads,
+            cache_mode,
+            change: candidate.mean / baseline.mean - 1.0,
+            p: welch(&baseline, &candidate),
+            baseline,
+            candidate,
+        });
+    }
+    (comparisons, unmatched + candidate.len())
+}
@@ -0,0 +1,326 @@
+use std::{fs, io, path::Path};
+
+use binary_search::{
+    BEytzinger, CompressedSTree, DynamicSTree, EqualRange, Eytzinger, LowerBound, Predecessor, PrefixLut, QueryKind,
+    RangeCount, STree, STreeMap, SortedVec, UpperBound, VebTree, WithPayloads,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    bench_search::{run_exps, Sink},
+    cache::{CacheControl, CacheMode},
+    metadata,
+};
+
+/// What a run measures. Experiments are read from the `[[experiment]]` tables of a TOML
+/// file, and every field but the name has a default:
+///
+/// ```toml
+/// [[experiment]]
+/// name = "s-tree-batch-sizes"
+/// structures = ["STree"]
+/// schemes = ["batch<", "batch_prefetch<128"]
+/// query_kinds = ["LowerBound"]
+/// sizes = { from = 10, to = 28, steps = 2 }
+/// distribution = "uniform"
+/// repetitions = 5
+/// threads = [1, 4]
+/// latency_samples = 10000
+/// cache_modes = ["warm", "cold"]
+/// ```
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Experiment {
+    pub name: String,
+    /// Names from `STRUCTURES`.
+    #[serde(default = "default_structures")]
+    pub structures: Vec<String>,
+    /// Schemes from `Searchable::all_funcs` whose short name contains one of these. With
+    /// none, the schemes of `Searchable::get_funcs`.
+    #[serde(default)]
+    pub schemes: Vec<String>,
+    /// Names from `QUERY_KINDS`.
+    #[serde(default = "default_query_kinds")]
+    pub query_kinds: Vec<String>,
+    #[serde(default)]
+    pub sizes: SizeRange,
+    #[serde(default)]
+    pub distribution: Distribution,
+    /// Number of queries per measurement, rounded up to a multiple of the batch sizes.
+    #[serde(default = "default_queries")]
+    pub queries: usize,
+    /// How many times each scheme is measured at each size.
+    #[serde(default = "default_repetitions")]
+    pub repetitions: usize,
+    /// Each measurement is made with each of these numbers of threads, which split the
+    /// queries between them.
+    #[serde(default = "default_threads")]
+    pub threads: Vec<usize>,
+    /// How many of the queries are also timed one by one, for the latency percentiles and
+    /// histogram of each single threaded result of a scheme without batches. 0 turns this
+    /// off.
+    #[serde(default = "default_latency_samples")]
+    pub latency_samples: usize,
+    /// Each measurement is made in each of these cache states.
+    #[serde(default = "default_cache_modes")]
+    pub cache_modes: Vec<CacheMode>,
+}
+
+impl Default for Experiment {
+    /// STree and CompressedSTree, from 16 B to 4 GiB.
+    fn default() -> Self {
+        Experiment {
+            name: "default".to_string(),
+            structures: default_structures(),
+            schemes: vec!(),
+            query_kinds: default_query_kinds(),
+            sizes: SizeRange::default(),
+            distribution: Distribution::default(),
+            queries: default_queries(),
+            repetitions: default_repetitions(),
+            threads: default_threads(),
+            latency_samples: default_latency_samples(),
+            cache_modes: default_cache_modes(),
+        }
+    }
+}
+
+fn default_structures() -> Vec<String> {
+    vec!("STree".to_string(), "CompressedSTree".to_string())
+}
+
+fn default_query_kinds() -> Vec<String> {
+    vec!("LowerBound".to_string())
+}
+
+fn default_queries() -> usize {
+    1_000_000
+}
+
+fn default_repetitions() -> usize {
+    1
+}
+
+fn default_threads() -> Vec<usize> {
+    vec!(1)
+}
+
+fn default_latency_samples() -> usize {
+    10_000
+}
+
+fn default_cache_modes() -> Vec<CacheMode> {
+    vec!(CacheMode::Warm)
+}
+
+/// Input sizes in bytes: every power of two from `2^from` to `2^to`, and `steps - 1`
+/// evenly spaced sizes between each two of them.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SizeRange {
+    pub from: u32,
+    pub to: u32,
+    #[serde(default = "default_steps")]
+    pub steps: usize,
+}
+
+impl Default for SizeRange {
+    fn default() -> Self {
+        SizeRange { from: 4, to: 32, steps: default_steps() }
+    }
+}
+
+fn default_steps() -> usize {
+    4
+}
+
+impl SizeRange {
+    pub fn sizes(&self) -> Vec<usize> {
+        let mut result = Vec::new();
+        for b in self.from..self.to {
+            let base = 1 << b;
+            result.extend((0..self.steps).map(|i| base * (self.steps + i) / self.steps));
+        }
+        result.push(1 << self.to);
+        result
+    }
+}
+
+/// How keys and queries are drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Distribution {
+    /// Uniform below `i32::MAX`.
+    #[default]
+    Uniform,
+    /// Uniform below twice the number of keys, so about every other value is a key.
+    Dense,
+    /// Uniform within 1024 clusters of 2^16 values each, which are placed uniformly.
+    Clustered,
+}
+
+impl Distribution {
+    /// `len` sorted keys and `n_queries` queries, from the same distribution.
+    fn generate(self, rng: &mut StdRng, len: usize, n_queries: usize) -> (Vec<u32>, Vec<u32>) {
+        let mut draw: Box<dyn FnMut(&mut StdRng) -> u32> = match self {
+            Distribution::Uniform => Box::new(|rng| rng.random_range(0..i32::MAX as u32)),
+            Distribution::Dense => {
+                let end = (2 * len).clamp(1, i32::MAX as usize) as u32;
+                Box::new(move |rng| rng.random_range(0..end))
+            }
+            Distribution::Clustered => {
+                const WIDTH: u32 = 1 << 16;
+                let centers: Vec<u32> = (0..1024).map(|_| rng.random_range(0..i32::MAX as u32 - WIDTH)).collect();
+                Box::new(move |rng| centers[rng.random_range(0..centers.len())] + rng.random_range(0..WIDTH))
+            }
+        };
+        let mut keys: Vec<u32> = (0..len).map(|_| draw(rng)).collect();
+        keys.sort_unstable();
+        let queries = (0..n_queries.next_multiple_of(256 * 3)).map(|_| draw(rng)).collect();
+        (keys, queries)
+    }
+}
+
+/// The structures an experiment can name, and how it measures each of them.
+macro_rules! structures {
+    ($($name:literal => $ty:ty,)*) => {
+        pub const STRUCTURES: &[&str] = &[$($name),*];
+
+        fn run_structure<K: QueryKind>(structure: &str, sink: &mut impl Sink, m: &Measurement) -> io::Result<()> {
+            match structure {
+                $($name => run_exps::<$ty, K>(sink, m),)*
+                _ => unreachable!("unknown structure {structure}"),
+            }
+        }
+    };
+}
+
+structures! {
+    "SortedVec" => SortedVec,
+    "Eytzinger" => Eytzinger,
+    "VebTree" => VebTree,
+    "BEytzinger" => BEytzinger,
+    "STree" => STree,
+    "CompressedSTree" => CompressedSTree,
+    "PrefixLut<STree,16>" => PrefixLut<STree, 16>,
+    "DynamicSTree<0>" => DynamicSTree<0>,
+    "DynamicSTree<1024>" => DynamicSTree<1024>,
+    "DynamicSTree<65536>" => DynamicSTree<65536>,
+    "WithPayloads<STree,u32>" => WithPayloads<STree, u32>,
+    "STreeMap<u32>" => STreeMap<u32>,
+    "WithPayloads<STree,u64>" => WithPayloads<STree, u64>,
+    "STreeMap<u64>" => STreeMap<u64>,
+}
+
+pub const QUERY_KINDS: &[&str] = &["LowerBound", "UpperBound", "Predecessor", "EqualRange", "RangeCount"];
+
+fn run_query_kind(kind: &str, structure: &str, sink: &mut impl Sink, m: &Measurement) -> io::Result<()> {
+    match kind {
+        "LowerBound" => run_structure::<LowerBound>(structure, sink, m),
+        "UpperBound" => run_structure::<UpperBound>(structure, sink, m),
+        "Predecessor" => run_structure::<Predecessor>(structure, sink, m),
+        "EqualRange" => run_structure::<EqualRange>(structure, sink, m),
+        "RangeCount" => run_structure::<RangeCount>(structure, sink, m),
+        _ => unreachable!("unknown query kind {kind}"),
+    }
+}
+
+/// What `run_exps` measures: the schemes of one structure, at one size.
+pub struct Measurement<'a> {
+    pub vals: &'a [u32],
+    pub queries: &'a [u32],
+    pub size: usize,
+    /// Patterns from `Experiment::schemes`.
+    pub schemes: &'a [String],
+    pub repetitions: usize,
+    pub threads: &'a [usize],
+    pub latency_samples: usize,
+    pub caches: &'a [CacheControl],
+}
+
+/// Structure names without spaces, so `PrefixLut<STree, 16>` can be written either way.
+fn normalize(name: &str) -> String {
+    name.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+impl Experiment {
+    /// Checks the names and numbers, and brings structure names to the form of `STRUCTURES`.
+    fn validate(mut self) -> Result<Self, String> {
+        let name = self.name.clone();
+        for structure in &mut self.structures {
+            *structure = normalize(structure);
+            if !STRUCTURES.contains(&structure.as_str()) {
+                return Err(format!("experiment {name}: unknown structure {structure}, expected one of {}", STRUCTURES.join(", ")));
+            }
+        }
+        if let Some(kind) = self.query_kinds.iter().find(|kind| !QUERY_KINDS.contains(&kind.as_str())) {
+            return Err(format!("experiment {name}: unknown query kind {kind}, expected one of {}", QUERY_KINDS.join(", ")));
+        }
+        if self.sizes.from < 2 || self.sizes.from > self.sizes.to || self.sizes.to > 40 || self.sizes.steps == 0 {
+            return Err(format!("experiment {name}: sizes need 2 <= from <= to <= 40 and steps >= 1"));
+        }
+        if self.repetitions == 0 || self.queries == 0 || self.threads.is_empty() || self.threads.contains(&0) {
+            return Err(format!("experiment {name}: queries, repetitions and threads have to be positive"));
+        }
+        if self.cache_modes.is_empty() {
+            return Err(format!("experiment {name}: cache_modes needs at least one mode"));
+        }
+        Ok(self)
+    }
+
+    /// Measures everything the experiment names that `sink` has no result for yet. The keys
+    /// and queries come from `seed`, so they are the same when a run is resumed.
+    pub fn run(&self, sink: &mut impl Sink, seed: u64) -> io::Result<()> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        // Without a last level cache to size the buffers by, one large enough for most CPUs.
+        let llc_size = metadata::llc_size().unwrap_or(64 << 20);
+        let caches: Vec<CacheControl> = self.cache_modes.iter()
+            .map(|&mode| CacheControl::new(mode, llc_size))
+            .collect::<io::Result<_>>()?;
+        for size in self.sizes.sizes() {
+            let len = size / size_of::<u32>();
+            let (vals, queries) = self.distribution.generate(&mut rng, len, self.queries);
+            let m = Measurement {
+                vals: &vals,
+                queries: &queries,
+                size,
+                schemes: &self.schemes,
+                repetitions: self.repetitions,
+                threads: &self.threads,
+                latency_samples: self.latency_samples,
+                caches: &caches,
+            };
+            for kind in &self.query_kinds {
+                for structure in &self.structures {
+                    run_query_kind(kind, structure, sink, &m)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    experiment: Vec<Experiment>,
+}
+
+/// Reads the experiments of a TOML file, or the ones of them named in `names`.
+pub fn load(path: &Path, names: &[String]) -> io::Result<Vec<Experiment>> {
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {err}", path.display()));
+    let config: Config = toml::from_str(&fs::read_to_string(path)?).map_err(|err| invalid(err.to_string()))?;
+    let experiments: Vec<Experiment> = config.experiment.into_iter()
+        .map(Experiment::validate)
+        .collect::<Result<_, _>>()
+        .map_err(invalid)?;
+    for (i, e) in experiments.iter().enumerate() {
+        if experiments[..i].iter().any(|other| other.name == e.name) {
+            return Err(invalid(format!("experiment {} is defined more than once", e.name)));
+        }
+    }
+    if let Some(name) = names.iter().find(|name| !experiments.iter().any(|e| &e.name == *name)) {
+        return Err(invalid(format!("no experiment named {name}")));
+    }
+    Ok(experiments.into_iter().filter(|e| names.is_empty() || names.contains(&e.name)).collect())
+}
@@ -0,0 +1,99 @@
+use std::hint::black_box;
+
+use binary_search::{SearchScheme, Searchable};
+
+use crate::timer::{self, Timer};
+
+/// Sub-buckets per power of two in a histogram, so a bucket is at most 12.5% wide.
+const SUB_BUCKETS: u32 = 8;
+
+/// Queries latencies within `[lower_ns, upper_ns)`.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Bucket {
+    pub lower_ns: f64,
+    pub upper_ns: f64,
+    pub count: u64,
+}
+
+/// The latency histogram of the result at index `result` of a run, as one line of
+/// `histograms.jsonl`.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Histogram {
+    pub result: usize,
+    pub buckets: Vec<Bucket>,
+}
+
+/// Times the first `n` of `queries` one at a time, each waiting for the answer to the one
+/// before, and returns their latencies in ns, sorted.
+///
+/// Only meaningful for schemes that answer one query at a time; a batched one would spend
+/// a whole batch on each.
+pub fn sample<I: Searchable>(searchable: &I, scheme: &dyn SearchScheme<I>, queries: &[u32], n: usize) -> Vec<f64> {
+    let timer = Timer::get();
+    // Zero, but only at run time, so each query depends on the answer to the one before.
+    let mask = black_box(0);
+    let mut answer = 0;
+    let mut latencies: Vec<f64> = queries.iter().take(n)
+        .map(|&q| {
+            let q = q ^ (answer & mask);
+            let start = timer::start();
+            answer = scheme.query_one(searchable, q);
+            let stop = timer::stop();
+            timer.ns(start, stop)
+        })
+        .collect();
+    latencies.sort_by(f64::total_cmp);
+    latencies
+}
+
+/// The `p`-quantile of sorted latencies, by the nearest rank.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Counts sorted latencies into buckets that double every `SUB_BUCKETS` buckets, from
+/// 1 ns up; faster ones go into one bucket from 0. Empty buckets are left out.
+pub fn histogram(sorted: &[f64]) -> Vec<Bucket> {
+    let bounds = |ns: f64| {
+        if ns < 1.0 {
+            return (0.0, 1.0);
+        }
+        let power = ns.log2().floor();
+        let base = power.exp2();
+        let sub = ((ns / base - 1.0) * SUB_BUCKETS as f64).floor();
+        let width = base / SUB_BUCKETS as f64;
+        (base + sub * width, base + (sub + 1.0) * width)
+    };
+    let mut buckets: Vec<Bucket> = Vec::new();
+    for &ns in sorted {
+        let (lower_ns, upper_ns) = bounds(ns);
+        match buckets.last_mut() {
+            Some(bucket) if bucket.lower_ns == lower_ns => bucket.count += 1,
+            _ => buckets.push(Bucket { lower_ns, upper_ns, count: 1 }),
+        }
+    }
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<f64> = (1..=1000).map(f64::from).collect();
+        assert_eq!(percentile(&sorted, 0.5), 500.0);
+        assert_eq!(percentile(&sorted, 0.999), 999.0);
+        assert_eq!(percentile(&sorted, 1.0), 1000.0);
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&[7.0], 0.99), 7.0);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let buckets = histogram(&[0.0, 0.5, 1.0, 8.0, 8.9, 9.0, 100.0]);
+        let bounds: Vec<(f64, f64, u64)> = buckets.iter().map(|b| (b.lower_ns, b.upper_ns, b.count)).collect();
+        assert_eq!(bounds, [(0.0, 1.0, 2), (1.0, 1.125, 1), (8.0, 9.0, 2), (9.0, 10.0, 1), (96.0, 104.0, 1)]);
+    }
+}
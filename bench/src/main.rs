@@ -0,0 +1,237 @@
+#![allow(unused)]
+
+use std::{io, path::PathBuf, process::ExitCode};
+
+use bench_search::Sink;
+use clap::{Parser, Subcommand};
+use experiment::Experiment;
+use metadata::RunMetadata;
+use output::Format;
+use store::{format_timestamp, Filter, Run, Store};
+
+mod bench_search;
+mod cache;
+mod compare;
+mod experiment;
+mod latency;
+mod metadata;
+mod output;
+mod report;
+mod stats;
+mod store;
+mod timer;
+
+/// Benchmarks the search structures, and keeps the results of every run.
+#[derive(Parser)]
+struct Cli {
+    /// Directory of the result store.
+    #[arg(long, global = true, default_value_os_t = store::default_dir())]
+    store: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the benchmark and saves it as a new run, or one run per experiment of a config
+    /// file. This is the default.
+    Run {
+        /// TOML file of experiments to run. Without one, the default experiment runs.
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Only runs the experiment of the config file with this name. Can be given more than once.
+        #[arg(long, requires = "config")]
+        experiment: Vec<String>,
+        /// A label to find the run by later, such as a branch name. The name of the
+        /// experiment by default.
+        #[arg(long)]
+        tag: Option<String>,
+        /// How to write the results.
+        #[arg(long, value_enum, default_value_t)]
+        format: Format,
+        /// How many times to measure each scheme at each size, instead of what the experiment
+        /// says. `compare` needs at least two to tell a change from noise.
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        repetitions: Option<u64>,
+        /// Continues the run with this id, which did not finish, skipping the results it has.
+        #[arg(long, conflicts_with_all = ["config", "tag", "format", "repetitions"])]
+        resume: Option<String>,
+    },
+    /// Lists the runs that match the filter.
+    List(Filter),
+    /// Prints the results that match the filter, from every run that does.
+    Query(Filter),
+    /// Compares the results of two runs, and fails if the candidate has regressed.
+    Compare {
+        /// Id of the run to compare against.
+        baseline: String,
+        /// Id of the run to check.
+        candidate: String,
+        /// Smallest slowdown, in percent of the baseline latency, that counts as a regression.
+        #[arg(long, default_value_t = 5.0)]
+        threshold: f64,
+        /// Largest p-value at which a change counts as significant.
+        #[arg(long, default_value_t = 0.05)]
+        alpha: f64,
+    },
+    /// Plots the results of a run as SVG files, and writes them to an HTML report.
+    Report {
+        /// The run to plot, the latest one by default.
+        #[arg(long)]
+        run: Option<String>,
+        /// Directory to write to, `plots/<run id>` by default.
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Title of the report, the tag or id of the run by default.
+        #[arg(long)]
+        title: Option<String>,
+        /// Upper end of the latency axis in ns, just above the slowest result by default.
+        #[arg(long)]
+        ymax: Option<f64>,
+    },
+    /// Prints the latency histograms of a run as CSV, one line per bucket.
+    Histogram {
+        /// The run to export, the latest one that matches the filter by default.
+        #[arg(long)]
+        run: Option<String>,
+        #[command(flatten)]
+        filter: Filter,
+    },
+}
+
+fn main() -> io::Result<ExitCode> {
+    let cli = Cli::parse();
+    let store = Store::open(&cli.store)?;
+    let command = cli.command.unwrap_or(Command::Run {
+        config: None,
+        experiment: vec!(),
+        tag: None,
+        format: Format::default(),
+        repetitions: None,
+        resume: None,
+    });
+    match command {
+        Command::Run { config, experiment, tag, format, repetitions, resume } => {
+            if let Some(id) = resume {
+                run(store.resume(&id, &RunMetadata::collect())?)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            let experiments = match config {
+                Some(path) => experiment::load(&path, &experiment)?,
+                None => vec!(Experiment::default()),
+            };
+            for mut experiment in experiments {
+                if let Some(repetitions) = repetitions {
+                    experiment.repetitions = repetitions as usize;
+                }
+                let tag = tag.clone().unwrap_or_else(|| experiment.name.clone());
+                run(store.start(Run::new(Some(tag), format, experiment, RunMetadata::collect()))?)?;
+            }
+        }
+        Command::List(filter) => {
+            for entry in store.find(&filter)? {
+                let (date, time) = format_timestamp(entry.timestamp);
+                let commit = entry.git_commit.as_deref().map_or("", |c| &c[..c.len().min(10)]);
+                println!(
+                    "{}  {date} {time}  {:<12} {:<16} {commit:<10}  {} results",
+                    entry.id,
+                    entry.tag.as_deref().unwrap_or("-"),
+                    entry.hostname.as_deref().unwrap_or("-"),
+                    entry.n_results,
+                );
+            }
+        }
+        Command::Query(filter) => {
+            for entry in store.find(&filter)? {
+                let run = store.load(&entry.id)?;
+                for result in store.results(&run)?.iter().filter(|result| filter.matches_result(result)) {
+                    let p99 = result.p99_ns.map_or("-".to_string(), |p99| format!("{p99:.1}"));
+                    println!(
+                        "{}  {:>12}  {:>9.2} ns  p99 {p99:>9} ns  {:<11}  {}  {}  {}",
+                        run.id, result.size, result.latency, result.cache_mode.name(), result.query_kind, result.searchable_name, result.scheme_name,
+                    );
+                }
+            }
+        }
+        Command::Compare { baseline, candidate, threshold, alpha } => {
+            let (baseline, candidate) = (store.load(&baseline)?, store.load(&candidate)?);
+            let differences = baseline.metadata.differences(&candidate.metadata);
+            if !differences.is_empty() {
+                println!("The runs differ in {}", differences.join(", "));
+            }
+            let (comparisons, unmatched) = compare::compare(&store.results(&baseline)?, &store.results(&candidate)?);
+            let threshold = threshold / 100.0;
+            let mut regressions = 0;
+            for c in &comparisons {
+                let verdict = if c.is_regression(threshold, alpha) {
+                    regressions += 1;
+                    "slower"
+                } else if c.is_improvement(threshold, alpha) {
+                    "faster"
+                } else {
+                    ""
+                };
+                let p = c.p.map_or("-".to_string(), |p| format!("{p:.3}"));
+                println!(
+                    "{:>12}  {:>9.2} ns  {:>9.2} ns  {:>+7.1}%  p {p:>5}  {verdict:<6}  {:<11}  {}  {}  {}",
+                    c.size, c.baseline.mean, c.candidate.mean, c.change * 100.0, c.cache_mode.name(), c.query_kind, bench_search::short_name(&c.searchable_name), bench_search::short_name(&c.scheme_name),
+                );
+            }
+            println!("{} compared, {regressions} regressed, {unmatched} in only one run", comparisons.len());
+            if regressions > 0 {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Report { run, out, title, ymax } => {
+            let run = store.load(&latest(&store, run, &Filter::default())?)?;
+            let out = out.unwrap_or_else(|| store::default_dir().with_file_name("plots").join(&run.id));
+            let title = title.or_else(|| run.tag.clone()).unwrap_or_else(|| run.id.clone());
+            for path in report::write(&out, &run, &store.results(&run)?, &title, ymax)? {
+                println!("Wrote {}", path.display());
+            }
+        }
+        Command::Histogram { run, filter } => {
+            let run = store.load(&latest(&store, run, &filter)?)?;
+            let results = store.results(&run)?;
+            let mut out = csv::Writer::from_writer(io::stdout().lock());
+            out.write_record(["run_id", "searchable_name", "scheme_name", "query_kind", "size", "threads", "cache_mode", "lower_ns", "upper_ns", "count"])?;
+            for histogram in store.histograms(&run.id)? {
+                let result = &results[histogram.result];
+                if !filter.matches_result(result) {
+                    continue;
+                }
+                for bucket in histogram.buckets {
+                    out.serialize((
+                        &run.id, &result.searchable_name, &result.scheme_name, &result.query_kind, result.size, result.threads,
+                        result.cache_mode.name(), bucket.lower_ns, bucket.upper_ns, bucket.count,
+                    ))?;
+                }
+            }
+            out.flush()?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `id`, or else the id of the latest complete run that matches `filter`.
+fn latest(store: &Store, id: Option<String>, filter: &Filter) -> io::Result<String> {
+    match id {
+        Some(id) => Ok(id),
+        None => Ok(store.find(filter)?.pop()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the store has no complete runs that match"))?
+            .id),
+    }
+}
+
+/// Measures what the run has no results for yet, then adds it to the index.
+fn run(mut session: store::Session) -> io::Result<()> {
+    let run = session.run();
+    let (id, seed) = (run.id.clone(), run.seed);
+    // `Store::resume` only returns runs that have an experiment.
+    let experiment = run.experiment.clone().expect("a run in progress has an experiment");
+    println!("Run {id} of experiment {}, {} results so far", experiment.name, session.results().len());
+    experiment.run(&mut session, seed)?;
+    session.finish()?;
+    println!("Saved run {id}");
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use std::{fs, path::Path, time::{SystemTime, UNIX_EPOCH}};
+
+/// Describes the machine and build a run was measured on, so results can be read
+/// without access to either.
+///
+/// Everything is read from `/proc` and `/sys`; a field is `None` where those are not
+/// available.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RunMetadata {
+    pub hostname: Option<String>,
+    pub cpu_model: Option<String>,
+    /// Current frequency of the core running the benchmark, in MHz.
+    pub cpu_mhz: Option<f64>,
+    pub cpu_max_mhz: Option<f64>,
+    pub governor: Option<String>,
+    pub caches: Vec<Cache>,
+    /// Base page size in bytes.
+    pub page_size: Option<usize>,
+    /// The selected transparent huge page mode, such as `madvise`.
+    pub thp: Option<String>,
+    pub kernel: Option<String>,
+    pub rustc: String,
+    pub profile: String,
+    pub rustflags: String,
+    pub target_features: Vec<String>,
+    pub nightly: bool,
+    pub git_commit: Option<String>,
+    /// Whether tracked files differed from `git_commit` when the benchmark was built.
+    pub git_dirty: bool,
+    /// Seconds since the Unix epoch at the start of the run.
+    pub timestamp: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Cache {
+    pub level: u32,
+    /// `Data`, `Instruction` or `Unified`.
+    pub kind: String,
+    pub size: usize,
+}
+
+impl RunMetadata {
+    pub fn collect() -> Self {
+        let cpufreq = Path::new("/sys/devices/system/cpu/cpu0/cpufreq");
+        let khz_to_mhz = |file: &str| read(cpufreq.join(file)).and_then(|khz| khz.parse::<f64>().ok()).map(|khz| khz / 1000.0);
+
+        RunMetadata {
+            hostname: read("/proc/sys/kernel/hostname"),
+            cpu_model: cpuinfo("model name"),
+            cpu_mhz: khz_to_mhz("scaling_cur_freq").or_else(|| cpuinfo("cpu MHz")?.parse().ok()),
+            cpu_max_mhz: khz_to_mhz("cpuinfo_max_freq"),
+            governor: read(cpufreq.join("scaling_governor")),
+            caches: caches(),
+            page_size: page_size(),
+            thp: read("/sys/kernel/mm/transparent_hugepage/enabled")
+                .and_then(|modes| Some(modes.split_once('[')?.1.split_once(']')?.0.to_string())),
+            kernel: read("/proc/sys/kernel/osrelease"),
+            rustc: env!("BENCH_RUSTC_VERSION").to_string(),
+            profile: env!("BENCH_PROFILE").to_string(),
+            rustflags: env!("BENCH_RUSTFLAGS").to_string(),
+            target_features: env!("BENCH_TARGET_FEATURES").split(',').filter(|f| !f.is_empty()).map(String::from).collect(),
+            nightly: cfg!(feature = "nightly"),
+            git_commit: Some(env!("BENCH_GIT_COMMIT")).filter(|c| !c.is_empty()).map(String::from),
+            git_dirty: env!("BENCH_GIT_DIRTY") == "true",
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs()),
+        }
+    }
+
+    /// The names of the fields that show `other` was measured on a different machine or
+    /// build than `self`.
+    pub fn differences(&self, other: &RunMetadata) -> Vec<&'static str> {
+        let fields = [
+            ("hostname", self.hostname == other.hostname),
+            ("cpu", self.cpu_model == other.cpu_model),
+            ("kernel", self.kernel == other.kernel),
+            ("rustc", self.rustc == other.rustc),
+            ("profile", self.profile == other.profile),
+            ("rustflags", self.rustflags == other.rustflags),
+            ("nightly feature", self.nightly == other.nightly),
+            ("commit", self.git_commit == other.git_commit && self.git_dirty == other.git_dirty),
+        ];
+        fields.into_iter().filter(|&(_, same)| !same).map(|(name, _)| name).collect()
+    }
+}
+
+fn read(path: impl AsRef<Path>) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// The value of `field` for the first CPU in `/proc/cpuinfo`.
+fn cpuinfo(field: &str) -> Option<String> {
+    read("/proc/cpuinfo")?.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim() == field)
+        .map(|(_, value)| value.trim().to_string())
+}
+
+/// Size in bytes of the largest data cache of the first CPU.
+pub fn llc_size() -> Option<usize> {
+    caches().into_iter().filter(|cache| cache.kind != "Instruction").map(|cache| cache.size).max()
+}
+
+/// The caches of the first CPU, with sizes such as `48K` in bytes.
+fn caches() -> Vec<Cache> {
+    let Ok(entries) = fs::read_dir("/sys/devices/system/cpu/cpu0/cache") else {
+        return vec!();
+    };
+    let mut caches: Vec<Cache> = entries.flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("index"))
+        .filter_map(|entry| {
+            let dir = entry.path();
+            Some(Cache {
+                level: read(dir.join("level"))?.parse().ok()?,
+                kind: read(dir.join("type"))?,
+                size: parse_size(&read(dir.join("size"))?)?,
+            })
+        })
+        .collect();
+    caches.sort_by(|a, b| (a.level, &a.kind).cmp(&(b.level, &b.kind)));
+    caches
+}
+
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, unit) = size.split_at(size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len()));
+    let scale = match unit {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return None,
+    };
+    Some(digits.parse::<usize>().ok()? * scale)
+}
+
+/// The page size of the first mapping of this process.
+fn page_size() -> Option<usize> {
+    let smaps = read("/proc/self/smaps")?;
+    let kb = smaps.lines().find_map(|line| line.strip_prefix("KernelPageSize:"))?;
+    Some(kb.trim().strip_suffix("kB")?.trim().parse::<usize>().ok()? * 1024)
+}
@@ -0,0 +1,453 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use parquet::{
+    basic::Compression,
+    data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type},
+    file::{
+        properties::WriterProperties,
+        reader::{FileReader, SerializedFileReader},
+        writer::SerializedFileWriter,
+    },
+    record::{Field, RowAccessor},
+    schema::parser::parse_message_type,
+};
+
+use crate::{bench_search::QueryResult, cache::CacheMode};
+
+/// How the results of a run are written.
+///
+/// Every format holds the same flat rows: the run id followed by the fields of
+/// `QueryResult`. JSON and Parquet files can only be written once the run is complete, so
+/// until then their results go to `results.partial.jsonl` next to them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// One JSON array.
+    #[default]
+    Json,
+    /// One JSON object per line.
+    Jsonl,
+    /// A header line, then one line per result.
+    Csv,
+    /// One row group.
+    Parquet,
+}
+
+impl Format {
+    /// The file results are written to while the run is in progress, and its format.
+    pub fn stream(self, path: &Path) -> (PathBuf, Format) {
+        match self {
+            Format::Json | Format::Parquet => (path.with_extension("partial.jsonl"), Format::Jsonl),
+            Format::Jsonl | Format::Csv => (path.to_path_buf(), self),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Jsonl => "jsonl",
+            Format::Csv => "csv",
+            Format::Parquet => "parquet",
+        }
+    }
+}
+
+const PARQUET_SCHEMA: &str = "
+message result {
+    required binary run_id (STRING);
+    required binary searchable_name (STRING);
+    required binary scheme_name (STRING);
+    required binary query_kind (STRING);
+    required int64 size;
+    required int64 duration_ns;
+    required double latency;
+    required int64 threads;
+    optional double p50_ns;
+    optional double p90_ns;
+    optional double p99_ns;
+    optional double p999_ns;
+    required binary cache_mode (STRING);
+}";
+
+/// A result as it is written, with the run it belongs to.
+#[derive(serde::Serialize)]
+struct Row<'a> {
+    run_id: &'a str,
+    searchable_name: &'a str,
+    scheme_name: &'a str,
+    query_kind: &'a str,
+    size: usize,
+    duration_ns: u64,
+    latency: f64,
+    threads: usize,
+    p50_ns: Option<f64>,
+    p90_ns: Option<f64>,
+    p99_ns: Option<f64>,
+    p999_ns: Option<f64>,
+    cache_mode: CacheMode,
+}
+
+impl<'a> Row<'a> {
+    fn new(run_id: &'a str, result: &'a QueryResult) -> Self {
+        Row {
+            run_id,
+            searchable_name: &result.searchable_name,
+            scheme_name: &result.scheme_name,
+            query_kind: &result.query_kind,
+            size: result.size,
+            duration_ns: result.duration_ns,
+            latency: result.latency,
+            threads: result.threads,
+            p50_ns: result.p50_ns,
+            p90_ns: result.p90_ns,
+            p99_ns: result.p99_ns,
+            p999_ns: result.p999_ns,
+            cache_mode: result.cache_mode,
+        }
+    }
+}
+
+/// Writes the results of one run to a file as they come in, flushing each one so that a
+/// run that is killed keeps every result it completed.
+pub struct ResultWriter {
+    run_id: String,
+    path: PathBuf,
+    format: Format,
+    out: Output,
+}
+
+enum Output {
+    Jsonl(BufWriter<File>),
+    Csv(Box<csv::Writer<File>>),
+}
+
+impl ResultWriter {
+    pub fn create(path: &Path, format: Format, run_id: &str) -> io::Result<Self> {
+        Self::resume(path, format, run_id, &[])
+    }
+
+    /// Starts over from `results`, as read back by `read_partial`, replacing what was
+    /// written before. This drops a partial last line.
+    pub fn resume(path: &Path, format: Format, run_id: &str, results: &[QueryResult]) -> io::Result<Self> {
+        let (stream, stream_format) = format.stream(path);
+        // Rewrite next to the old file and swap it in, so the results are never only in memory.
+        let tmp = stream.with_extension("tmp");
+        let out = match stream_format {
+            Format::Csv => Output::Csv(Box::new(csv::Writer::from_path(&tmp)?)),
+            _ => Output::Jsonl(BufWriter::new(File::create(&tmp)?)),
+        };
+        let mut writer = ResultWriter { run_id: run_id.to_string(), path: path.to_path_buf(), format, out };
+        for result in results {
+            writer.write(result)?;
+        }
+        fs::rename(&tmp, &stream)?;
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, result: &QueryResult) -> io::Result<()> {
+        let row = Row::new(&self.run_id, result);
+        match &mut self.out {
+            Output::Jsonl(file) => {
+                serde_json::to_writer(&mut *file, &row)?;
+                file.write_all(b"\n")?;
+                file.flush()
+            }
+            Output::Csv(writer) => {
+                writer.serialize(row)?;
+                writer.flush()
+            }
+        }
+    }
+
+    /// Completes the file, given every result written to it.
+    pub fn finish(self, results: &[QueryResult]) -> io::Result<()> {
+        match self.out {
+            Output::Jsonl(file) => file.into_inner()?.sync_all()?,
+            Output::Csv(writer) => writer.into_inner().map_err(|err| err.into_error())?.sync_all()?,
+        }
+        match self.format {
+            Format::Json => {
+                let rows: Vec<Row> = results.iter().map(|result| Row::new(&self.run_id, result)).collect();
+                let mut file = BufWriter::new(File::create(&self.path)?);
+                serde_json::to_writer(&mut file, &rows)?;
+                file.into_inner()?.sync_all()?;
+            }
+            Format::Parquet => write_parquet(&self.path, &self.run_id, results).map_err(io::Error::other)?,
+            Format::Jsonl | Format::Csv => return Ok(()),
+        }
+        fs::remove_file(self.format.stream(&self.path).0)
+    }
+}
+
+fn write_parquet(path: &Path, run_id: &str, results: &[QueryResult]) -> parquet::errors::Result<()> {
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let props = Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+    let file = File::create(path)?;
+    let mut writer = SerializedFileWriter::new(file.try_clone()?, schema, props)?;
+    let mut row_group = writer.next_row_group()?;
+
+    let strings = |field: fn(&QueryResult) -> &str| -> Vec<ByteArray> {
+        results.iter().map(|r| field(r).into()).collect()
+    };
+    let string_columns = [
+        vec![ByteArray::from(run_id); results.len()],
+        strings(|r| &r.searchable_name),
+        strings(|r| &r.scheme_name),
+        strings(|r| &r.query_kind),
+    ];
+    for values in string_columns {
+        let mut column = row_group.next_column()?.unwrap();
+        column.typed::<ByteArrayType>().write_batch(&values, None, None)?;
+        column.close()?;
+    }
+    for values in [results.iter().map(|r| r.size as i64).collect::<Vec<_>>(), results.iter().map(|r| r.duration_ns as i64).collect()] {
+        let mut column = row_group.next_column()?.unwrap();
+        column.typed::<Int64Type>().write_batch(&values, None, None)?;
+        column.close()?;
+    }
+    let mut column = row_group.next_column()?.unwrap();
+    column.typed::<DoubleType>().write_batch(&results.iter().map(|r| r.latency).collect::<Vec<_>>(), None, None)?;
+    column.close()?;
+    let mut column = row_group.next_column()?.unwrap();
+    column.typed::<Int64Type>().write_batch(&results.iter().map(|r| r.threads as i64).collect::<Vec<_>>(), None, None)?;
+    column.close()?;
+    let percentiles: [fn(&QueryResult) -> Option<f64>; 4] = [|r| r.p50_ns, |r| r.p90_ns, |r| r.p99_ns, |r| r.p999_ns];
+    for field in percentiles {
+        let values: Vec<f64> = results.iter().filter_map(field).collect();
+        let levels: Vec<i16> = results.iter().map(|r| i16::from(field(r).is_some())).collect();
+        let mut column = row_group.next_column()?.unwrap();
+        column.typed::<DoubleType>().write_batch(&values, Some(&levels), None)?;
+        column.close()?;
+    }
+    let mut column = row_group.next_column()?.unwrap();
+    column.typed::<ByteArrayType>().write_batch(&strings(|r| r.cache_mode.name()), None, None)?;
+    column.close()?;
+
+    row_group.close()?;
+    writer.close()?;
+    Ok(file.sync_all()?)
+}
+
+/// Reads the results a `ResultWriter` has written so far, whether or not it finished.
+pub fn read_partial(path: &Path, format: Format) -> io::Result<Vec<QueryResult>> {
+    let (stream, stream_format) = format.stream(path);
+    if fs::exists(&stream)? {
+        read_results(&stream, stream_format)
+    } else if fs::exists(path)? {
+        read_results(path, format)
+    } else {
+        Ok(vec!())
+    }
+}
+
+/// Reads the results written by a `ResultWriter`.
+///
+/// A JSON Lines or CSV file may end in a partial line if the run was killed while writing
+/// it; that line is skipped.
+pub fn read_results(path: &Path, format: Format) -> io::Result<Vec<QueryResult>> {
+    let file = File::open(path)?;
+    match format {
+        Format::Json => Ok(serde_json::from_reader(BufReader::new(file))?),
+        Format::Jsonl => {
+            let mut results = Vec::new();
+            for line in BufReader::new(file).lines() {
+                match serde_json::from_str(&line?) {
+                    Ok(result) => results.push(result),
+                    Err(err) if err.is_eof() => break,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Ok(results)
+        }
+        Format::Csv => {
+            // A number cut short still parses, so only whole lines are read.
+            let mut data = Vec::new();
+            BufReader::new(file).read_to_end(&mut data)?;
+            data.truncate(data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1));
+            let mut results = Vec::new();
+            let mut rows = csv::Reader::from_reader(data.as_slice()).into_deserialize();
+            while let Some(result) = rows.next() {
+                match result {
+                    Ok(result) => results.push(result),
+                    Err(_) if rows.next().is_none() => break,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Ok(results)
+        }
+        Format::Parquet => {
+            let reader = SerializedFileReader::new(file).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+            reader.into_iter()
+                .map(|row| {
+                    let row = row.map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+                    let field = |err| io::Error::new(ErrorKind::InvalidData, err);
+                    let optional = |i| match row.get_column_iter().nth(i) {
+                        Some((_, Field::Double(value))) => Some(*value),
+                        _ => None,
+                    };
+                    Ok(QueryResult {
+                        searchable_name: row.get_string(1).map_err(field)?.clone(),
+                        scheme_name: row.get_string(2).map_err(field)?.clone(),
+                        query_kind: row.get_string(3).map_err(field)?.clone(),
+                        size: row.get_long(4).map_err(field)? as usize,
+                        duration_ns: row.get_long(5).map_err(field)? as u64,
+                        latency: row.get_double(6).map_err(field)?,
+                        threads: row.get_long(7).map_err(field)? as usize,
+                        p50_ns: optional(8),
+                        p90_ns: optional(9),
+                        p99_ns: optional(10),
+                        p999_ns: optional(11),
+                        // Missing from results written before there were cache modes.
+                        cache_mode: match row.get_column_iter().nth(12) {
+                            Some((_, Field::Str(name))) => CacheMode::ALL.into_iter().find(|mode| mode.name() == name)
+                                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("unknown cache mode {name}")))?,
+                            _ => CacheMode::default(),
+                        },
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bench_{}_{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn result(i: usize) -> QueryResult {
+        QueryResult {
+            searchable_name: "binary_search::searches::s_tree::STree".to_string(),
+            scheme_name: format!("scheme {i}"),
+            query_kind: "LowerBound".to_string(),
+            size: 1024 << i,
+            duration_ns: 123_456 + i as u64,
+            latency: 1.25 * i as f64,
+            threads: 1 + i % 2,
+            p50_ns: i.is_multiple_of(2).then_some(12.5),
+            p90_ns: i.is_multiple_of(2).then_some(20.0),
+            p99_ns: None,
+            p999_ns: None,
+            cache_mode: CacheMode::ALL[i % 3],
+        }
+    }
+
+    /// Results as JSON, to compare them field by field.
+    fn json(results: &[QueryResult]) -> Vec<String> {
+        results.iter().map(|r| serde_json::to_string(r).unwrap()).collect()
+    }
+
+    /// Writes the first `n` results and stops without finishing, like a run that was killed.
+    fn killed_after(path: &Path, format: Format, n: usize) {
+        let mut writer = ResultWriter::create(path, format, "run").unwrap();
+        for i in 0..n {
+            writer.write(&result(i)).unwrap();
+        }
+    }
+
+    fn expected(n: usize) -> Vec<String> {
+        json(&(0..n).map(result).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_jsonl_cut_off_line() {
+        let dir = temp_dir("jsonl");
+        let path = dir.join("results.jsonl");
+        killed_after(&path, Format::Jsonl, 3);
+        let whole = fs::read(&path).unwrap();
+        let last_line = whole[..whole.len() - 1].iter().rposition(|&b| b == b'\n').unwrap() + 1;
+
+        // Every cut within the last line loses only that line, but a line that is only missing
+        // its newline is complete.
+        for end in last_line..whole.len() - 1 {
+            fs::write(&path, &whole[..end]).unwrap();
+            assert_eq!(json(&read_partial(&path, Format::Jsonl).unwrap()), expected(2), "cut at {end}");
+        }
+        fs::write(&path, &whole[..whole.len() - 1]).unwrap();
+        assert_eq!(json(&read_partial(&path, Format::Jsonl).unwrap()), expected(3));
+
+        // Resuming drops the partial line before the next result.
+        fs::write(&path, &whole[..whole.len() - 20]).unwrap();
+        let results = read_partial(&path, Format::Jsonl).unwrap();
+        let mut writer = ResultWriter::resume(&path, Format::Jsonl, "run", &results).unwrap();
+        writer.write(&result(2)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), whole);
+
+        // A line that is broken rather than cut short is an error.
+        fs::write(&path, [&whole[..], b"{\"run_id\": 1}\n"].concat()).unwrap();
+        assert!(read_partial(&path, Format::Jsonl).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_csv_cut_off_line() {
+        let dir = temp_dir("csv");
+        let path = dir.join("results.csv");
+        killed_after(&path, Format::Csv, 3);
+        let whole = fs::read(&path).unwrap();
+        let last_line = whole[..whole.len() - 1].iter().rposition(|&b| b == b'\n').unwrap() + 1;
+        assert_eq!(json(&read_partial(&path, Format::Csv).unwrap()), expected(3));
+
+        // Only lines up to the last newline count, even where a cut leaves a number that parses.
+        for end in last_line..whole.len() {
+            fs::write(&path, &whole[..end]).unwrap();
+            assert_eq!(json(&read_partial(&path, Format::Csv).unwrap()), expected(2), "cut at {end}");
+        }
+
+        // A file cut within the header has no results.
+        fs::write(&path, &whole[..10]).unwrap();
+        assert!(read_partial(&path, Format::Csv).unwrap().is_empty());
+
+        fs::write(&path, &whole[..whole.len() - 5]).unwrap();
+        let results = read_partial(&path, Format::Csv).unwrap();
+        let mut writer = ResultWriter::resume(&path, Format::Csv, "run", &results).unwrap();
+        writer.write(&result(2)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), whole);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_journal_swap() {
+        let dir = temp_dir("journal");
+        for format in [Format::Json, Format::Parquet] {
+            let path = dir.join(format!("results.{}", format.extension()));
+            let journal = path.with_extension("partial.jsonl");
+
+            // Until the run finishes, its results are only in the journal.
+            killed_after(&path, format, 2);
+            assert!(!fs::exists(&path).unwrap() && fs::exists(&journal).unwrap(), "{format:?}");
+            let mut cut = fs::read(&journal).unwrap();
+            cut.extend_from_slice(b"{\"run_id\":\"run\",\"searchable_na");
+            fs::write(&journal, cut).unwrap();
+            let results = read_partial(&path, format).unwrap();
+            assert_eq!(json(&results), expected(2), "{format:?}");
+
+            let mut writer = ResultWriter::resume(&path, format, "run", &results).unwrap();
+            let results: Vec<QueryResult> = (0..3).map(result).collect();
+            writer.write(&results[2]).unwrap();
+            assert_eq!(json(&read_partial(&path, format).unwrap()), expected(3), "{format:?}");
+
+            // Finishing writes the file, then removes the journal.
+            writer.finish(&results).unwrap();
+            assert!(fs::exists(&path).unwrap() && !fs::exists(&journal).unwrap(), "{format:?}");
+            assert_eq!(json(&read_results(&path, format).unwrap()), expected(3), "{format:?}");
+            assert_eq!(json(&read_partial(&path, format).unwrap()), expected(3), "{format:?}");
+
+            // A run killed after writing the file but before removing the journal still
+            // reads the journal, which has every result.
+            killed_after(&path, format, 3);
+            assert_eq!(json(&read_partial(&path, format).unwrap()), expected(3), "{format:?}");
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    bench_search::{short_name, QueryResult},
+    cache::CacheMode,
+    store::{format_timestamp, Run},
+};
+
+const WIDTH: f64 = 880.0;
+const HEIGHT: f64 = 560.0;
+const LEFT: f64 = 70.0;
+const RIGHT: f64 = 20.0;
+const TOP: f64 = 40.0;
+const BOTTOM: f64 = 50.0;
+
+/// Line colors, the matplotlib default cycle.
+const COLORS: [&str; 10] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f", "#bcbd22", "#17becf",
+];
+
+/// One line of a plot: the median latency of a scheme at each size it was measured at.
+struct Series {
+    name: String,
+    points: Vec<(usize, f64)>,
+}
+
+/// Writes `<out>/<query kind>.svg` for every query kind in `results`, and `<out>/report.html`
+/// with the same plots inline, the run metadata and tables of every result.
+///
+/// The y axis goes up to `ymax` ns, or to just above the slowest result.
+pub fn write(out: &Path, run: &Run, results: &[QueryResult], title: &str, ymax: Option<f64>) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(out)?;
+    let mut kinds: Vec<&str> = results.iter().map(|r| r.query_kind.as_str()).collect();
+    kinds.sort();
+    kinds.dedup();
+
+    let mut written = Vec::new();
+    let mut sections = String::new();
+    for kind in kinds {
+        let series = series(results.iter().filter(|r| r.query_kind == kind));
+        let plot = svg(&format!("{title}: {kind}"), &series, run, ymax);
+        let path = out.join(format!("{kind}.svg"));
+        fs::write(&path, &plot)?;
+        written.push(path);
+        write!(sections, "<h2>{}</h2>\n{plot}\n{}", escape(kind), table(&series)).unwrap();
+    }
+
+    let path = out.join("report.html");
+    fs::write(&path, html(title, run, &sections))?;
+    written.push(path);
+    Ok(written)
+}
+
+/// The results grouped by scheme, number of threads and cache mode, in the order they were
+/// first measured.
+fn series<'a>(results: impl Iterator<Item = &'a QueryResult>) -> Vec<Series> {
+    let mut by_scheme: Vec<(String, BTreeMap<usize, Vec<f64>>)> = Vec::new();
+    for result in results {
+        let mut name = short_name(&result.scheme_name);
+        if result.threads > 1 {
+            name = format!("{name}, {} threads", result.threads);
+        }
+        if result.cache_mode != CacheMode::Warm {
+            name = format!("{name}, {}", result.cache_mode.name());
+        }
+        let i = match by_scheme.iter().position(|(n, _)| *n == name) {
+            Some(i) => i,
+            None => {
+                by_scheme.push((name, BTreeMap::new()));
+                by_scheme.len() - 1
+            }
+        };
+        by_scheme[i].1.entry(result.size).or_default().push(result.latency);
+    }
+    by_scheme.into_iter()
+        .map(|(name, sizes)| Series {
+            name,
+            points: sizes.into_iter().map(|(size, latencies)| (size, median(latencies))).collect(),
+        })
+        .collect()
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len() % 2 == 1 { values[mid] } else { (values[mid - 1] + values[mid]) / 2.0 }
+}
+
+/// A line plot of latency against size, with a log2 x axis and a dashed marker at the
+/// size of each data cache.
+fn svg(title: &str, series: &[Series], run: &Run, ymax: Option<f64>) -> String {
+    let points = series.iter().flat_map(|s| &s.points);
+    let (min_size, max_size) = points.clone().fold((usize::MAX, 0), |(lo, hi), &(size, _)| (lo.min(size), hi.max(size)));
+    let (xmin, xmax) = ((min_size.max(1) as f64).log2().floor(), (max_size.max(2) as f64).log2().ceil());
+    let xmax = if xmax > xmin { xmax } else { xmin + 1.0 };
+    let ymax = ymax.unwrap_or_else(|| nice_ceil(points.map(|&(_, latency)| latency).fold(0.0, f64::max) * 1.05));
+
+    let (plot_w, plot_h) = (WIDTH - LEFT - RIGHT, HEIGHT - TOP - BOTTOM);
+    let x = |size: f64| LEFT + (size.log2() - xmin) / (xmax - xmin) * plot_w;
+    let y = |latency: f64| TOP + plot_h - latency.min(ymax) / ymax * plot_h;
+
+    let mut s = String::new();
+    writeln!(s, r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {WIDTH} {HEIGHT}" width="{WIDTH}" height="{HEIGHT}" font-family="sans-serif" font-size="12">"#).unwrap();
+    writeln!(s, r#"<rect width="100%" height="100%" fill="white"/>"#).unwrap();
+    writeln!(s, r#"<text x="{}" y="22" text-anchor="middle" font-size="16">{}</text>"#, WIDTH / 2.0, escape(title)).unwrap();
+
+    // Grid and axes. Every power of two gets a grid line; with many, only every other one a label.
+    let step = if xmax - xmin > 16.0 { 2 } else { 1 };
+    for (i, exp) in (xmin as u32..=xmax as u32).enumerate() {
+        let px = x(2f64.powi(exp as i32));
+        writeln!(s, r##"<line x1="{px:.1}" y1="{TOP}" x2="{px:.1}" y2="{}" stroke="#ddd"/>"##, TOP + plot_h).unwrap();
+        if i % step == 0 {
+            writeln!(s, r#"<text x="{px:.1}" y="{}" text-anchor="middle">{}</text>"#, TOP + plot_h + 16.0, format_bytes(1 << exp)).unwrap();
+        }
+    }
+    let ystep = nice_ceil(ymax / 8.0);
+    let decimals = (-ystep.log10().floor()).max(0.0) as usize;
+    for i in 0..=(ymax / ystep).floor() as u32 {
+        let latency = i as f64 * ystep;
+        let py = y(latency);
+        writeln!(s, r##"<line x1="{LEFT}" y1="{py:.1}" x2="{}" y2="{py:.1}" stroke="#ddd"/>"##, LEFT + plot_w).unwrap();
+        writeln!(s, r#"<text x="{}" y="{:.1}" text-anchor="end">{latency:.decimals$}</text>"#, LEFT - 6.0, py + 4.0).unwrap();
+    }
+    writeln!(s, r#"<rect x="{LEFT}" y="{TOP}" width="{plot_w}" height="{plot_h}" fill="none" stroke="black"/>"#).unwrap();
+    writeln!(s, r#"<text x="{}" y="{}" text-anchor="middle">Input size (bytes)</text>"#, LEFT + plot_w / 2.0, HEIGHT - 12.0).unwrap();
+    writeln!(s, r#"<text transform="translate(18 {}) rotate(-90)" text-anchor="middle">Inverse throughput (ns)</text>"#, TOP + plot_h / 2.0).unwrap();
+
+    for cache in run.metadata.caches.iter().filter(|c| c.kind != "Instruction" && c.size > 0) {
+        let px = x(cache.size as f64);
+        if (LEFT..=LEFT + plot_w).contains(&px) {
+            writeln!(s, r#"<line x1="{px:.1}" y1="{TOP}" x2="{px:.1}" y2="{}" stroke="red" stroke-dasharray="6 4"/>"#, TOP + plot_h).unwrap();
+            writeln!(s, r#"<text x="{:.1}" y="{}" fill="red" text-anchor="end">L{}</text>"#, px - 3.0, TOP + plot_h - 6.0, cache.level).unwrap();
+        }
+    }
+
+    for (i, series) in series.iter().enumerate() {
+        let path: Vec<String> = series.points.iter().map(|&(size, latency)| format!("{:.1},{:.1}", x(size as f64), y(latency))).collect();
+        writeln!(s, r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="2"/>"#, path.join(" "), COLORS[i % COLORS.len()]).unwrap();
+    }
+
+    // Legend in the upper left corner.
+    let legend_w = series.iter().map(|s| s.name.len()).max().unwrap_or(0) as f64 * 6.6 + 40.0;
+    writeln!(s, r##"<rect x="{}" y="{}" width="{legend_w:.1}" height="{}" fill="white" fill-opacity="0.8" stroke="#999"/>"##, LEFT + 8.0, TOP + 8.0, series.len() as f64 * 18.0 + 8.0).unwrap();
+    for (i, series) in series.iter().enumerate() {
+        let py = TOP + 22.0 + i as f64 * 18.0;
+        writeln!(s, r#"<line x1="{}" y1="{py}" x2="{}" y2="{py}" stroke="{}" stroke-width="2"/>"#, LEFT + 14.0, LEFT + 34.0, COLORS[i % COLORS.len()]).unwrap();
+        writeln!(s, r#"<text x="{}" y="{}">{}</text>"#, LEFT + 40.0, py + 4.0, escape(&series.name)).unwrap();
+    }
+    s.push_str("</svg>");
+    s
+}
+
+/// A table of the median latency of every scheme at every size.
+fn table(series: &[Series]) -> String {
+    let mut sizes: Vec<usize> = series.iter().flat_map(|s| s.points.iter().map(|&(size, _)| size)).collect();
+    sizes.sort();
+    sizes.dedup();
+
+    let mut s = String::from("<table>\n<tr><th>Size</th>");
+    for series in series {
+        write!(s, "<th>{}</th>", escape(&series.name)).unwrap();
+    }
+    s.push_str("</tr>\n");
+    for size in sizes {
+        write!(s, "<tr><td>{}</td>", format_bytes(size)).unwrap();
+        for series in series {
+            match series.points.iter().find(|&&(s, _)| s == size) {
+                Some((_, latency)) => write!(s, "<td>{latency:.2}</td>").unwrap(),
+                None => s.push_str("<td></td>"),
+            }
+        }
+        s.push_str("</tr>\n");
+    }
+    s.push_str("</table>\n");
+    s
+}
+
+fn html(title: &str, run: &Run, sections: &str) -> String {
+    let m = &run.metadata;
+    let (date, time) = format_timestamp(m.timestamp);
+    let caches: Vec<String> = m.caches.iter().map(|c| format!("L{} {} {}", c.level, c.kind, format_bytes(c.size))).collect();
+    let commit = m.git_commit.as_deref().map(|c| if m.git_dirty { format!("{c} (dirty)") } else { c.to_string() });
+    let rows = [
+        ("Run", Some(run.id.clone())),
+        ("Tag", run.tag.clone()),
+        ("Started", Some(format!("{date} {time} UTC"))),
+        ("Host", m.hostname.clone()),
+        ("CPU", m.cpu_model.clone()),
+        ("Frequency", m.cpu_mhz.map(|mhz| format!("{mhz:.0} MHz, at most {} MHz", m.cpu_max_mhz.map_or("?".to_string(), |max| format!("{max:.0}"))))),
+        ("Governor", m.governor.clone()),
+        ("Caches", Some(caches.join(", "))),
+        ("Page size", m.page_size.map(format_bytes)),
+        ("Transparent huge pages", m.thp.clone()),
+        ("Kernel", m.kernel.clone()),
+        ("Compiler", Some(m.rustc.clone())),
+        ("Profile", Some(m.profile.clone())),
+        ("Flags", Some(m.rustflags.clone())),
+        ("Nightly feature", Some(m.nightly.to_string())),
+        ("Commit", commit),
+    ];
+
+    let mut s = String::new();
+    writeln!(s, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>", escape(title)).unwrap();
+    s.push_str("<style>\nbody { font-family: sans-serif; margin: 2em; }\ntable { border-collapse: collapse; margin-bottom: 2em; }\nth, td { border: 1px solid #ccc; padding: 2px 8px; }\ntd { text-align: right; font-variant-numeric: tabular-nums; }\ntable.metadata td { text-align: left; }\nsvg { max-width: 100%; height: auto; }\n</style>\n</head>\n<body>\n");
+    writeln!(s, "<h1>{}</h1>\n<table class=\"metadata\">", escape(title)).unwrap();
+    for (name, value) in rows {
+        if let Some(value) = value {
+            writeln!(s, "<tr><th>{name}</th><td>{}</td></tr>", escape(&value)).unwrap();
+        }
+    }
+    s.push_str("</table>\n<p>Latencies are in ns per query, the median where a size was measured more than once.</p>\n");
+    s.push_str(sections);
+    s.push_str("</body>\n</html>\n");
+    s
+}
+
+/// A size in bytes as `16 B`, `48 KiB` or `1.5 MiB`.
+fn format_bytes(size: usize) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value.fract() == 0.0 { format!("{value} {}", units[unit]) } else { format!("{value:.2} {}", units[unit]) }
+}
+
+/// The smallest of 1, 2 or 5 times a power of ten that is at least `value`.
+fn nice_ceil(value: f64) -> f64 {
+    if value <= 0.0 {
+        return 1.0;
+    }
+    let magnitude = 10f64.powf(value.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].into_iter().map(|m| m * magnitude).find(|&v| v >= value).unwrap()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
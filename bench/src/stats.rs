@@ -0,0 +1,119 @@
+/// The mean and spread of repeated measurements of one thing.
+pub struct Summary {
+    pub n: usize,
+    pub mean: f64,
+    /// The sample variance, 0 for a single measurement.
+    pub variance: f64,
+}
+
+impl Summary {
+    pub fn new(values: &[f64]) -> Self {
+        let n = values.len();
+        let mean = values.iter().sum::<f64>() / n as f64;
+        let variance = if n > 1 { values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64 } else { 0.0 };
+        Summary { n, mean, variance }
+    }
+}
+
+/// The two-sided p-value of Welch's t-test that `a` and `b` have the same mean, or `None`
+/// if either has fewer than two measurements.
+pub fn welch(a: &Summary, b: &Summary) -> Option<f64> {
+    if a.n < 2 || b.n < 2 {
+        return None;
+    }
+    let (va, vb) = (a.variance / a.n as f64, b.variance / b.n as f64);
+    if va + vb == 0.0 {
+        return Some(if a.mean == b.mean { 1.0 } else { 0.0 });
+    }
+    let t = (a.mean - b.mean) / (va + vb).sqrt();
+    // Welch–Satterthwaite degrees of freedom.
+    let df = (va + vb).powi(2) / (va.powi(2) / (a.n - 1) as f64 + vb.powi(2) / (b.n - 1) as f64);
+    Some(incomplete_beta(df / (df + t * t), df / 2.0, 0.5))
+}
+
+/// The regularized incomplete beta function I_x(a, b), by its continued fraction.
+fn incomplete_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    // The fraction converges quickly below the mean of the distribution; above it, use the symmetry.
+    if x > (a + 1.0) / (a + b + 2.0) {
+        return 1.0 - incomplete_beta(1.0 - x, b, a);
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp() / a;
+
+    // Modified Lentz's method.
+    const TINY: f64 = 1e-300;
+    let (mut c, mut d) = (1.0, 1.0 - (a + b) * x / (a + 1.0));
+    d = 1.0 / if d.abs() < TINY { TINY } else { d };
+    let mut f = d;
+    for m in 1..300 {
+        let m = m as f64;
+        for numerator in [
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)),
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)),
+        ] {
+            d = 1.0 + numerator * d;
+            d = 1.0 / if d.abs() < TINY { TINY } else { d };
+            c = 1.0 + numerator / c;
+            c = if c.abs() < TINY { TINY } else { c };
+            f *= c * d;
+        }
+        if (c * d - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+    front * f
+}
+
+/// ln Γ(x) for x > 0, by the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // Reflection, Γ(x) Γ(1 - x) = π / sin(πx).
+        return (std::f64::consts::PI / (std::f64::consts::PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + G + 0.5;
+    let sum = COEFFICIENTS[0] + COEFFICIENTS[1..].iter().enumerate().map(|(i, c)| c / (x + i as f64 + 1.0)).sum::<f64>();
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_welch() {
+        // t = -3.523 with 7.09 degrees of freedom; the p-value is from integrating the t density.
+        let a = Summary::new(&[10.1, 10.3, 9.9, 10.0, 10.2]);
+        let b = Summary::new(&[10.4, 10.6, 10.5, 10.9, 10.3]);
+        let p = welch(&a, &b).unwrap();
+        assert!((p - 0.009_499).abs() < 1e-5, "{p}");
+
+        assert_eq!(welch(&a, &Summary::new(&[10.0])), None);
+        assert_eq!(welch(&Summary::new(&[1.0, 1.0]), &Summary::new(&[1.0, 1.0])), Some(1.0));
+        assert!((welch(&a, &a).unwrap() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_ln_gamma() {
+        for (x, gamma) in [(0.5, std::f64::consts::PI.sqrt()), (1.0, 1.0), (5.0, 24.0), (10.5, 1_133_278.388_7)] {
+            assert!((ln_gamma(x) - f64::ln(gamma)).abs() < 1e-9, "{x}");
+        }
+    }
+}
@@ -0,0 +1,476 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use rand::Rng;
+
+use crate::{
+    bench_search::{QueryResult, Sink},
+    experiment::Experiment,
+    latency::{Bucket, Histogram},
+    metadata::RunMetadata,
+    output::{read_partial, read_results, Format, ResultWriter},
+};
+
+/// One benchmark run, as stored in `runs/<id>/run.json`. Its results are next to it.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Run {
+    pub id: String,
+    pub tag: Option<String>,
+    // Runs from before a field existed lack it, and still have to load.
+    #[serde(default)]
+    pub format: Format,
+    /// Seeds the keys and queries, so a resumed run measures the same ones.
+    #[serde(default)]
+    pub seed: u64,
+    /// What the run measures. Runs from before experiments did not store it, so they can be
+    /// read but not resumed.
+    #[serde(default)]
+    pub experiment: Option<Experiment>,
+    pub metadata: RunMetadata,
+}
+
+impl Run {
+    /// A run with a new id, named after its start time so ids sort by date.
+    pub fn new(tag: Option<String>, format: Format, experiment: Experiment, metadata: RunMetadata) -> Self {
+        let (date, time) = format_timestamp(metadata.timestamp);
+        let id = format!("{}-{}-{:08x}", date.replace('-', ""), time.replace(':', ""), rand::rng().random::<u32>());
+        Run { id, tag, format, seed: rand::rng().random(), experiment: Some(experiment), metadata }
+    }
+}
+
+/// A line of `index.jsonl`: enough of a run to find it without reading it.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct IndexEntry {
+    pub id: String,
+    pub tag: Option<String>,
+    pub timestamp: u64,
+    pub hostname: Option<String>,
+    pub git_commit: Option<String>,
+    pub searchables: Vec<String>,
+    pub schemes: Vec<String>,
+    pub sizes: Vec<usize>,
+    pub n_results: usize,
+}
+
+impl IndexEntry {
+    fn new(run: &Run, results: &[QueryResult]) -> Self {
+        fn sorted<T: Ord>(mut v: Vec<T>) -> Vec<T> {
+            v.sort();
+            v.dedup();
+            v
+        }
+        IndexEntry {
+            id: run.id.clone(),
+            tag: run.tag.clone(),
+            timestamp: run.metadata.timestamp,
+            hostname: run.metadata.hostname.clone(),
+            git_commit: run.metadata.git_commit.clone(),
+            searchables: sorted(results.iter().map(|r| r.searchable_name.clone()).collect()),
+            schemes: sorted(results.iter().map(|r| r.scheme_name.clone()).collect()),
+            sizes: sorted(results.iter().map(|r| r.size).collect()),
+            n_results: results.len(),
+        }
+    }
+}
+
+/// Selects runs, and results within them. Every field that is set has to match.
+#[derive(Default, clap::Args)]
+pub struct Filter {
+    /// Only results whose searchable or scheme name contains this.
+    #[arg(long)]
+    pub scheme: Option<String>,
+    /// Only results for this input size in bytes.
+    #[arg(long)]
+    pub size: Option<usize>,
+    /// Only runs started on or after this day, as YYYY-MM-DD in UTC.
+    #[arg(long, value_parser = parse_date)]
+    pub since: Option<u64>,
+    /// Only runs started before the end of this day, as YYYY-MM-DD in UTC.
+    #[arg(long, value_parser = parse_date)]
+    pub until: Option<u64>,
+    /// Only runs with this tag.
+    #[arg(long)]
+    pub tag: Option<String>,
+}
+
+impl Filter {
+    pub fn matches_run(&self, entry: &IndexEntry) -> bool {
+        let names = entry.searchables.iter().chain(&entry.schemes);
+        self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until + SECONDS_PER_DAY)
+            && self.tag.as_ref().is_none_or(|tag| entry.tag.as_ref() == Some(tag))
+            && self.size.is_none_or(|size| entry.sizes.contains(&size))
+            && self.scheme.as_ref().is_none_or(|scheme| names.into_iter().any(|name| name.contains(scheme.as_str())))
+    }
+
+    pub fn matches_result(&self, result: &QueryResult) -> bool {
+        self.size.is_none_or(|size| result.size == size)
+            && self.scheme.as_ref().is_none_or(|scheme| {
+                result.searchable_name.contains(scheme.as_str()) || result.scheme_name.contains(scheme.as_str())
+            })
+    }
+}
+
+/// A directory of runs that are only ever added, with an index of all of them.
+///
+/// ```text
+/// <dir>/index.jsonl                   one IndexEntry per line, in the order the runs finished
+/// <dir>/runs/<id>/run.json            the Run
+/// <dir>/runs/<id>/results.<format>    its results
+/// <dir>/runs/<id>/histograms.jsonl    one latency Histogram per line, for the results that have one
+/// ```
+///
+/// A run that was started but not finished has a directory but no index entry.
+pub struct Store {
+    dir: PathBuf,
+}
+
+impl Store {
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join("runs"))?;
+        Ok(Store { dir })
+    }
+
+    fn run_dir(&self, id: &str) -> PathBuf {
+        self.dir.join("runs").join(id)
+    }
+
+    fn results_path(&self, run: &Run) -> PathBuf {
+        self.run_dir(&run.id).join(format!("results.{}", run.format.extension()))
+    }
+
+    fn histograms_path(&self, id: &str) -> PathBuf {
+        self.run_dir(id).join("histograms.jsonl")
+    }
+
+    /// Writes the run, and opens its results for writing.
+    pub fn start(&self, run: Run) -> io::Result<Session<'_>> {
+        let dir = self.run_dir(&run.id);
+        fs::create_dir(&dir)?;
+        let mut f = BufWriter::new(File::create(dir.join("run.json"))?);
+        serde_json::to_writer_pretty(&mut f, &run)?;
+        f.into_inner()?.sync_all()?;
+        let writer = ResultWriter::create(&self.results_path(&run), run.format, &run.id)?;
+        let histograms = BufWriter::new(File::create(self.histograms_path(&run.id))?);
+        Ok(Session { store: self, run, writer, results: Vec::new(), histograms })
+    }
+
+    /// Continues a run that did not finish, keeping the results it has.
+    ///
+    /// Fails if the run is complete, if it predates stored experiments, or if `metadata`
+    /// shows a different machine or build, whose results could not be compared with the ones
+    /// already there.
+    pub fn resume(&self, id: &str, metadata: &RunMetadata) -> io::Result<Session<'_>> {
+        if self.index()?.iter().any(|entry| entry.id == id) {
+            return Err(io::Error::new(ErrorKind::AlreadyExists, format!("run {id} is already complete")));
+        }
+        let run = self.load(id)?;
+        if run.experiment.is_none() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("run {id} does not say what it measures, so it can't be resumed")));
+        }
+        let differences = run.metadata.differences(metadata);
+        if !differences.is_empty() {
+            let message = format!("run {id} was measured with a different {}", differences.join(", "));
+            return Err(io::Error::new(ErrorKind::InvalidInput, message));
+        }
+        let path = self.results_path(&run);
+        let results = read_partial(&path, run.format)?;
+        let writer = ResultWriter::resume(&path, run.format, &run.id, &results)?;
+
+        // Histograms are written after their result, so there can be one too many.
+        let path = self.histograms_path(&run.id);
+        let tmp = path.with_extension("tmp");
+        let mut histograms = BufWriter::new(File::create(&tmp)?);
+        for histogram in self.histograms(&run.id)?.iter().filter(|h| h.result < results.len()) {
+            serde_json::to_writer(&mut histograms, histogram)?;
+            histograms.write_all(b"\n")?;
+        }
+        histograms.flush()?;
+        histograms.get_ref().sync_all()?;
+        fs::rename(&tmp, &path)?;
+        Ok(Session { store: self, run, writer, results, histograms })
+    }
+
+    pub fn index(&self) -> io::Result<Vec<IndexEntry>> {
+        let f = match File::open(self.dir.join("index.jsonl")) {
+            Ok(f) => f,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec!()),
+            Err(err) => return Err(err),
+        };
+        BufReader::new(f).lines()
+            .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    }
+
+    pub fn load(&self, id: &str) -> io::Result<Run> {
+        let f = File::open(self.run_dir(id).join("run.json"))?;
+        Ok(serde_json::from_reader(BufReader::new(f))?)
+    }
+
+    pub fn results(&self, run: &Run) -> io::Result<Vec<QueryResult>> {
+        read_results(&self.results_path(run), run.format)
+    }
+
+    /// The latency histograms of a run, by the index of their result. A line that was cut
+    /// short when the run was killed is left out.
+    pub fn histograms(&self, id: &str) -> io::Result<Vec<Histogram>> {
+        let data = match fs::read_to_string(self.histograms_path(id)) {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec!()),
+            Err(err) => return Err(err),
+        };
+        data[..data.rfind('\n').map_or(0, |i| i + 1)].lines()
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    /// The runs that match `filter`, oldest first.
+    pub fn find(&self, filter: &Filter) -> io::Result<Vec<IndexEntry>> {
+        let mut entries: Vec<IndexEntry> = self.index()?.into_iter().filter(|entry| filter.matches_run(entry)).collect();
+        entries.sort_by_key(|entry| entry.timestamp);
+        Ok(entries)
+    }
+}
+
+/// A run in progress. Every result is on disk as soon as it is pushed, so a run that is
+/// killed can be resumed from where it stopped.
+pub struct Session<'a> {
+    store: &'a Store,
+    run: Run,
+    writer: ResultWriter,
+    results: Vec<QueryResult>,
+    histograms: BufWriter<File>,
+}
+
+impl Session<'_> {
+    pub fn run(&self) -> &Run {
+        &self.run
+    }
+
+    /// Completes the results, then adds the run to the index, so the index only names
+    /// complete runs.
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.finish(&self.results)?;
+        self.histograms.into_inner()?.sync_all()?;
+        let mut line = serde_json::to_vec(&IndexEntry::new(&self.run, &self.results))?;
+        line.push(b'\n');
+        let mut index = OpenOptions::new().create(true).append(true).open(self.store.dir.join("index.jsonl"))?;
+        index.write_all(&line)
+    }
+}
+
+impl Sink for Session<'_> {
+    fn results(&self) -> &[QueryResult] {
+        &self.results
+    }
+
+    fn push(&mut self, result: QueryResult, histogram: Vec<Bucket>) -> io::Result<()> {
+        self.writer.write(&result)?;
+        if !histogram.is_empty() {
+            serde_json::to_writer(&mut self.histograms, &Histogram { result: self.results.len(), buckets: histogram })?;
+            self.histograms.write_all(b"\n")?;
+            self.histograms.flush()?;
+        }
+        self.results.push(result);
+        Ok(())
+    }
+}
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Parses a `YYYY-MM-DD` day to the timestamp of its start in UTC.
+fn parse_date(date: &str) -> Result<u64, String> {
+    let invalid = || format!("expected a date as YYYY-MM-DD, got `{date}`");
+    let mut parts = date.splitn(3, '-').map(|part| part.parse::<i64>().map_err(|_| invalid()));
+    let (year, month, day) = (parts.next().ok_or_else(invalid)??, parts.next().ok_or_else(invalid)??, parts.next().ok_or_else(invalid)??);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let month_len = match month {
+        2 => 28 + i64::from(leap),
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1..=12).contains(&month) || !(1..=month_len).contains(&day) {
+        return Err(invalid());
+    }
+    // Days since 1970-01-01 in the proleptic Gregorian calendar, counting years from March.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    u64::try_from(days).map(|days| days * SECONDS_PER_DAY).map_err(|_| invalid())
+}
+
+/// The UTC date and time of a timestamp, as `YYYY-MM-DD` and `HH:MM:SS`.
+pub fn format_timestamp(timestamp: u64) -> (String, String) {
+    let (days, secs) = ((timestamp / SECONDS_PER_DAY) as i64 + 719468, timestamp % SECONDS_PER_DAY);
+    // The inverse of `parse_date`.
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (
+        format!("{year:04}-{month:02}-{day:02}"),
+        format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60),
+    )
+}
+
+/// Path of the default store, `results/` next to the bench crate.
+pub fn default_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap().join("results")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::experiment::SizeRange;
+
+    use super::*;
+
+    fn temp_store(name: &str) -> (Store, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("bench_{}_{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        (Store::open(&dir).unwrap(), dir)
+    }
+
+    /// Two sizes, every default scheme of SortedVec, two repetitions.
+    fn experiment() -> Experiment {
+        Experiment {
+            structures: vec!("SortedVec".to_string()),
+            sizes: SizeRange { from: 6, to: 7, steps: 1 },
+            queries: 256,
+            repetitions: 2,
+            latency_samples: 0,
+            ..Experiment::default()
+        }
+    }
+
+    /// Starts a run of `experiment()` and measures all of it, without finishing.
+    fn measured(store: &Store, format: Format) -> Session<'_> {
+        let run = Run::new(None, format, experiment(), RunMetadata::collect());
+        let mut session = store.start(run).unwrap();
+        let seed = session.run.seed;
+        experiment().run(&mut session, seed).unwrap();
+        session
+    }
+
+    /// Resumes `id`, measures what is missing, and returns how many results were read back
+    /// and how many there are after that.
+    fn resume(store: &Store, id: &str) -> (usize, usize) {
+        let mut session = store.resume(id, &RunMetadata::collect()).unwrap();
+        let read = session.results.len();
+        let seed = session.run.seed;
+        session.run.experiment.clone().unwrap().run(&mut session, seed).unwrap();
+        let total = session.results.len();
+        session.finish().unwrap();
+        (read, total)
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_load_older_runs() {
+        let (store, dir) = temp_store("older");
+        let session = measured(&store, Format::Csv);
+        let (id, n) = (session.run.id.clone(), session.results.len());
+        drop(session);
+
+        // Written before runs had a seed or experiment.
+        let path = store.run_dir(&id).join("run.json");
+        let remove = |field: &str| {
+            let mut run: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+            run.as_object_mut().unwrap().remove(field);
+            fs::write(&path, serde_json::to_vec(&run).unwrap()).unwrap();
+        };
+        remove("experiment");
+        remove("seed");
+
+        let run = store.load(&id).unwrap();
+        assert!(run.experiment.is_none());
+        assert_eq!(store.results(&run).unwrap().len(), n);
+        let err = store.resume(&id, &RunMetadata::collect()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        remove("format");
+        assert_eq!(store.load(&id).unwrap().format, Format::Json);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_resume_killed_run() {
+        let (store, dir) = temp_store("killed");
+        for format in [Format::Jsonl, Format::Csv, Format::Json, Format::Parquet] {
+            let session = measured(&store, format);
+            let (id, n) = (session.run.id.clone(), session.results.len());
+            let path = format.stream(&store.results_path(&session.run)).0;
+            drop(session);
+
+            // Killed while writing the last result: it is measured again, and nothing else.
+            let data = fs::read(&path).unwrap();
+            fs::write(&path, &data[..data.len() - 4]).unwrap();
+            assert_eq!(resume(&store, &id), (n - 1, n), "{format:?}");
+
+            let run = store.load(&id).unwrap();
+            assert_eq!(store.results(&run).unwrap().len(), n, "{format:?}");
+            assert!(!fs::exists(path.with_extension("tmp")).unwrap(), "{format:?}");
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_resume_before_index_entry() {
+        let (store, dir) = temp_store("unindexed");
+        for format in [Format::Jsonl, Format::Json, Format::Parquet] {
+            // Killed after `Session::finish` completed the results but before it added the
+            // run to the index.
+            let Session { run, writer, results, .. } = measured(&store, format);
+            writer.finish(&results).unwrap();
+            assert!(!store.index().unwrap().iter().any(|entry| entry.id == run.id));
+
+            // Every result is read back and none is measured again.
+            let n = results.len();
+            assert_eq!(resume(&store, &run.id), (n, n), "{format:?}");
+            let entry = store.index().unwrap().into_iter().find(|entry| entry.id == run.id).unwrap();
+            assert_eq!(entry.n_results, n, "{format:?}");
+            assert_eq!(store.results(&run).unwrap().len(), n, "{format:?}");
+
+            let err = store.resume(&run.id, &RunMetadata::collect()).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::AlreadyExists, "{format:?}");
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert_eq!(parse_date("2000-02-29"), Ok(11_016 * SECONDS_PER_DAY));
+        assert_eq!(parse_date("2024-02-29"), Ok(19_782 * SECONDS_PER_DAY));
+        assert_eq!(parse_date("2024-03-01"), Ok(19_783 * SECONDS_PER_DAY));
+        for date in ["1969-12-31", "2023-02-29", "1900-02-29", "2023-04-31", "2023-13-01", "2023-00-10", "2023-01-00", "2023-01", "2023-01-xx", "abc", ""] {
+            assert!(parse_date(date).is_err(), "{date}");
+        }
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), ("1970-01-01".into(), "00:00:00".into()));
+        assert_eq!(format_timestamp(19_782 * SECONDS_PER_DAY + 86_399), ("2024-02-29".into(), "23:59:59".into()));
+        // Every day through 2100, which covers leap days and the skipped one in 2100.
+        for days in 0..47_847 {
+            let (date, time) = format_timestamp(days * SECONDS_PER_DAY + 3_723);
+            assert_eq!(time, "01:02:03");
+            assert_eq!(parse_date(&date), Ok(days * SECONDS_PER_DAY), "{date}");
+        }
+        assert_eq!(format_timestamp(47_846 * SECONDS_PER_DAY).0, "2100-12-31");
+    }
+}
@@ -0,0 +1,81 @@
+use std::{hint::black_box, sync::OnceLock, time::Instant};
+
+/// Times short stretches of code in ticks of the time stamp counter, or in ns where
+/// there is none.
+///
+/// `start` and `stop` are fenced, so the code between them neither starts before `start`
+/// nor is still running at `stop`.
+pub struct Timer {
+    ns_per_tick: f64,
+    /// Ticks between a `start` and a `stop` with nothing in between, which `ns` takes out.
+    overhead: u64,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub fn start() -> u64 {
+    use std::arch::x86_64::{_mm_lfence, _rdtsc};
+    // SAFETY: every x86_64 CPU has `rdtsc` and `lfence`.
+    unsafe {
+        _mm_lfence();
+        let ticks = _rdtsc();
+        _mm_lfence();
+        ticks
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub fn stop() -> u64 {
+    use std::arch::x86_64::{__rdtscp, _mm_lfence};
+    let mut aux = 0;
+    // SAFETY: `rdtscp` exists on every x86_64 CPU made since 2008, and the benchmark
+    // already needs AVX2.
+    unsafe {
+        let ticks = __rdtscp(&mut aux);
+        _mm_lfence();
+        ticks
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(always)]
+pub fn start() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(always)]
+pub fn stop() -> u64 {
+    start()
+}
+
+impl Timer {
+    /// The timer, calibrated on first use.
+    pub fn get() -> &'static Timer {
+        static TIMER: OnceLock<Timer> = OnceLock::new();
+        TIMER.get_or_init(Timer::calibrate)
+    }
+
+    fn calibrate() -> Self {
+        let (instant, ticks) = (Instant::now(), start());
+        while instant.elapsed().as_millis() < 50 {}
+        let ns_per_tick = instant.elapsed().as_nanos() as f64 / (stop() - ticks) as f64;
+
+        let mut empty: Vec<u64> = (0..10_001)
+            .map(|_| {
+                let ticks = start();
+                black_box(());
+                stop() - ticks
+            })
+            .collect();
+        empty.sort_unstable();
+        Timer { ns_per_tick, overhead: empty[empty.len() / 2] }
+    }
+
+    /// The time between a `start` and a `stop` that returned these, in ns.
+    pub fn ns(&self, start: u64, stop: u64) -> f64 {
+        stop.saturating_sub(start).saturating_sub(self.overhead) as f64 * self.ns_per_tick
+    }
+}
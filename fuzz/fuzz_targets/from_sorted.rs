@@ -0,0 +1,53 @@
+//! Builds the structures that can be streamed from a key file out of arbitrary bytes.
+//!
+//! A file that is not a whole number of sorted keys up to `MAX_KEY` has to be rejected with
+//! the right error, and any other file has to give the same structure as `Searchable::new`.
+//!
+//! `RUSTFLAGS="-C target-feature=+avx2,+popcnt" cargo +nightly fuzz run from_sorted`
+#![no_main]
+
+use std::io::Cursor;
+
+use binary_search::{searches::s_tree::MAX, BEytzinger, BuildError, Eytzinger, FromSorted, Ranked, STree, Searchable, SortedVec};
+use libfuzzer_sys::fuzz_target;
+
+fn check<S: FromSorted + Searchable + Ranked>(data: &[u8]) {
+    let result = S::from_sorted_reader(Cursor::new(data));
+    if !data.len().is_multiple_of(4) {
+        assert!(matches!(result, Err(BuildError::Io(_))), "{} trailing bytes accepted", data.len() % 4);
+        return;
+    }
+
+    let keys: Vec<u32> = data.chunks_exact(4).map(|key| u32::from_le_bytes(key.try_into().unwrap())).collect();
+    // The first bad key decides the error. A key above `MAX_KEY` can't also be unsorted,
+    // since the keys before it would be too large as well.
+    let unsorted = keys.windows(2).position(|pair| pair[0] > pair[1]).map(|i| i + 1);
+    let too_large = keys.iter().position(|&key| key > S::MAX_KEY);
+    let s = match (result, unsorted, too_large) {
+        (Err(BuildError::Unsorted { index }), Some(i), t) if t.is_none_or(|t| i < t) => return assert_eq!(index, i),
+        (Err(BuildError::KeyTooLarge { index, key, max }), u, Some(t)) if u.is_none_or(|u| t < u) => {
+            assert_eq!((index, key, max), (t, keys[t], S::MAX_KEY));
+            return;
+        }
+        (Ok(s), None, None) => s,
+        (result, unsorted, too_large) => {
+            panic!("got {:?} for keys unsorted at {unsorted:?}, too large at {too_large:?}", result.err())
+        }
+    };
+
+    let expected = S::new(&keys);
+    assert_eq!(s.len(), keys.len());
+    let sentinel = s.key_at(s.len());
+    for q in keys.iter().flat_map(|&k| [k.wrapping_sub(1), k, k.wrapping_add(1)]).chain([0, MAX, u32::MAX]) {
+        let key = keys.get(keys.partition_point(|&x| x < q)).copied().unwrap_or(sentinel);
+        assert_eq!(s.key_at(s.lower_bound(q)), key, "query {q}");
+        assert_eq!(s.lower_bound(q), expected.lower_bound(q), "query {q}");
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    check::<SortedVec>(data);
+    check::<Eytzinger>(data);
+    check::<STree>(data);
+    check::<BEytzinger>(data);
+});
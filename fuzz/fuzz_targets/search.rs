@@ -0,0 +1,73 @@
+//! Builds every structure from an arbitrary multiset of keys and checks every scheme of
+//! every query kind against `partition_point`.
+//!
+//! cargo-fuzz builds with debug assertions, so an index out of bounds in an unchecked read
+//! fails an assertion instead of reading past the end. It also replaces the flags from
+//! `.cargo/config.toml`, so pass them again to fuzz the AVX2 searches:
+//!
+//! `RUSTFLAGS="-C target-feature=+avx2,+popcnt" cargo +nightly fuzz run search`
+#![no_main]
+
+use arbitrary::Arbitrary;
+use binary_search::{
+    searches::s_tree::MAX, BEytzinger, CompressedSTree, DynamicSTree, Eytzinger, PrefixLut, STree, STreeMap,
+    SortedVec, VebTree, WithPayloads,
+};
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+use common::{check_all, check_payloads};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    /// Gaps between consecutive keys, so a zero is a duplicate and short inputs still make
+    /// many keys.
+    gaps: Vec<u8>,
+    /// Gaps are shifted left by this, modulo 24, to spread the keys over the whole range.
+    shift: u8,
+    /// The gaps are used this many times over, modulo 64, so short inputs reach the sizes
+    /// with more layers.
+    repeat: u8,
+    queries: Vec<u32>,
+}
+
+impl Input {
+    /// The sorted keys, capped at `top`.
+    fn keys(&self, top: u32) -> Vec<u32> {
+        let repeat = self.repeat as usize % 64 + 1;
+        self.gaps.iter().cycle().take(self.gaps.len() * repeat)
+            .scan(0u32, |key, &gap| {
+                *key = key.saturating_add((gap as u32) << (self.shift % 24)).min(top);
+                Some(*key)
+            })
+            .collect()
+    }
+
+    /// The queries, and each key together with its neighbours.
+    fn queries(&self, keys: &[u32]) -> Vec<u32> {
+        let near = keys.iter().flat_map(|&k| [k.wrapping_sub(1), k, k.wrapping_add(1)]);
+        self.queries.iter().copied().chain(near).chain([0, MAX, u32::MAX]).collect()
+    }
+}
+
+fuzz_target!(|input: Input| {
+    // These only reserve their sentinel `u32::MAX`, so the keys may run up to just below it.
+    let keys = input.keys(u32::MAX - 1);
+    let queries = input.queries(&keys);
+    check_all::<SortedVec>(&keys, &queries, u32::MAX);
+    check_all::<Eytzinger>(&keys, &queries, u32::MAX);
+    check_all::<VebTree>(&keys, &queries, u32::MAX);
+
+    // The rest pad their nodes with `MAX`, so the keys stay below it.
+    let keys = input.keys(MAX - 1);
+    let queries = input.queries(&keys);
+    check_all::<STree>(&keys, &queries, MAX);
+    check_all::<CompressedSTree>(&keys, &queries, MAX);
+    check_all::<BEytzinger>(&keys, &queries, MAX);
+    check_all::<PrefixLut<STree, 8>>(&keys, &queries, MAX);
+    check_all::<DynamicSTree<64>>(&keys, &queries, MAX);
+    check_payloads::<WithPayloads<STree, u32>>(&keys, &queries);
+    check_payloads::<STreeMap<u64>>(&keys, &queries);
+});
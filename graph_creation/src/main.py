@@ -6,28 +6,26 @@ from pathlib import Path
 from matplotlib.ticker import LogLocator
 from matplotlib.colors import to_rgba
 import re
+import json
 import argparse
 import os
 
 PYTHON_PROJECT_ROOT = os.path.dirname(os.path.abspath(__file__))
 PROJECT_ROOT = Path(os.path.dirname(PYTHON_PROJECT_ROOT)).parent
-RESULTS_JSON_PATH = PROJECT_ROOT / "results" / "results.json"
+STORE_PATH = PROJECT_ROOT / "results"
 SAVE_DIRECTORY = PROJECT_ROOT / "plots"
 SAVE_FILE_PATH = SAVE_DIRECTORY / "plot.svg"
 
 
-def caches() -> list[tuple[int,str]]:
-    sizes: list[tuple[int, str]] = []
-    # Note: index1 for me is the L1 instruction cache.
-    # Note: All read strings are eg 32K.
-    for i, name in [(0, "L1"), (2, "L2"), (3, "L3")]:
-        t = Path(f"/sys/devices/system/cpu/cpu0/cache/index{i}/size").read_text()
-        sizes.append((int(t[:-2]) * 1024, name))
-    return sizes
+def caches(metadata: dict) -> list[tuple[int,str]]:
+    """The data caches of the machine that ran the benchmark."""
+    return [
+        (cache["size"], f"L{cache['level']}")
+        for cache in metadata["caches"]
+        if cache["kind"] != "Instruction"
+    ]
 
-(l1_size, _), (l2_size, _), (l3_size, _) = caches()
-
-def plot(experiment_name: str, title: str, data: pd.DataFrame, ymax=None) -> None:
+def plot(experiment_name: str, title: str, data: pd.DataFrame, metadata: dict, ymax=None) -> None:
     fig, ax = plt.subplots(figsize=(11, 8))
     ax.set_title(title)
     ax.set_xlabel("Input size (bytes)")
@@ -50,7 +48,7 @@ def plot(experiment_name: str, title: str, data: pd.DataFrame, ymax=None) -> Non
         ax.set_ylim(0, ymax)
     ax.grid(True, alpha=0.5)
 
-    for size, name in caches():
+    for size, name in caches(metadata):
         if size > 0:
             ax.axvline(x=size, color="red", linestyle="--", zorder=0)
             ax.text(size, 10, f"{name} ", color="red", va="bottom", ha="right")
@@ -61,20 +59,40 @@ def plot(experiment_name: str, title: str, data: pd.DataFrame, ymax=None) -> Non
     fig.savefig(SAVE_FILE_PATH, bbox_inches="tight", dpi=300)
     plt.close(fig)
 
-def read_file():
-    data = pd.read_json(RESULTS_JSON_PATH)
-    return data
+def latest_run(store: Path) -> str:
+    with open(store / "index.jsonl") as f:
+        entries = [json.loads(line) for line in f if line.strip()]
+    return max(entries, key=lambda entry: entry["timestamp"])["id"]
+
+def read_run(store: Path, run_id: str) -> tuple[pd.DataFrame, dict]:
+    run_dir = store / "runs" / run_id
+    with open(run_dir / "run.json") as f:
+        run = json.load(f)
+    results = run_dir / f"results.{run['format']}"
+    readers = {
+        "json": pd.read_json,
+        "jsonl": lambda path: pd.read_json(path, lines=True),
+        "csv": pd.read_csv,
+        "parquet": pd.read_parquet,
+    }
+    return readers[run["format"]](results), run["metadata"]
 
 def main() -> None:
-    data = read_file()
+    parser = argparse.ArgumentParser(description="Plots a benchmark run from the result store.")
+    parser.add_argument("--store", type=Path, default=STORE_PATH, help="directory of the result store")
+    parser.add_argument("--run", help="id of the run to plot, the latest one by default")
+    args = parser.parse_args()
+
+    if not (args.store / "index.jsonl").exists():
+        return
+    data, metadata = read_run(args.store, args.run or latest_run(args.store))
     plot(
         "experiment",
         "experiment-title",
         data,
+        metadata,
         ymax=120,
     )
 
 if __name__ == "__main__":
-    if RESULTS_JSON_PATH.exists():
-        main()
-
+    main()
@@ -0,0 +1,40 @@
+#![allow(unused)]
+#![cfg_attr(feature = "nightly", allow(internal_features))]
+#![cfg_attr(feature = "nightly", feature(core_intrinsics, portable_simd))]
+
+//! Static search structures over sorted `u32` keys.
+//!
+//! Every structure is built with [`Searchable::new`]. The ones implementing [`Ranked`]
+//! answer lower bound, batched lower bound and range queries in terms of ranks; the
+//! schemes returned by [`Searchable::get_funcs`] are what the benchmark measures.
+//!
+//! The crate builds on stable. The `nightly` feature switches the node search and
+//! prefetching over to `portable_simd` and `core_intrinsics`, and adds the schemes that
+//! only exist with them.
+//!
+//! The searches read with unchecked indexing. Every such read is covered by a debug
+//! assertion, so `cargo test` and `cargo run --profile checked` check all indices, and
+//! `cargo +nightly miri test` runs the tests that are small enough for Miri.
+
+pub mod query;
+pub mod searches;
+mod utils;
+
+pub use query::{
+    query_kind::{EqualRange, Kind, LowerBound, Predecessor, QueryKind, RangeCount, UpperBound},
+    searchable::{SearchScheme, Searchable},
+};
+pub use searches::{
+    b_eytzinger::BEytzinger,
+    binary_search::SortedVec,
+    dynamic::DynamicSTree,
+    eytzinger::Eytzinger,
+    from_sorted::{BuildError, FromSorted},
+    payload::{Payload, PayloadLookup, STreeMap, WithPayloads},
+    persist::Persist,
+    prefix_lut::{PrefixLut, SubrangeSearch},
+    ranked::Ranked,
+    s_tree::STree,
+    s_tree_compressed::CompressedSTree,
+    veb_tree::VebTree,
+};
@@ -1 +1,2 @@
-pub mod bench_search;
+pub mod query_kind;
+pub mod searchable;
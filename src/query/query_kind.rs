@@ -0,0 +1,189 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// First key `>= q`.
+    LowerBound,
+    /// First key `> q`.
+    UpperBound,
+    /// Last key `<= q`.
+    Predecessor,
+    /// Number of keys `== q`.
+    EqualRange,
+    /// Number of keys in `[q, q + RANGE_WIDTH)`.
+    RangeCount,
+}
+
+impl Kind {
+    /// The value whose lower bound the search has to find.
+    pub fn target(self, q: u32) -> u32 {
+        match self {
+            Kind::LowerBound | Kind::EqualRange | Kind::RangeCount => q,
+            Kind::UpperBound | Kind::Predecessor => q.saturating_add(1),
+        }
+    }
+
+    /// Whether answering needs the rank of the lower bound, not just the key.
+    pub fn needs_rank(self) -> bool {
+        matches!(self, Kind::EqualRange | Kind::RangeCount)
+    }
+}
+
+/// Selects at compile time what a search scheme answers.
+pub trait QueryKind: 'static {
+    const KIND: Kind;
+
+    fn get_name() -> String {
+        format!("{:?}", Self::KIND)
+    }
+}
+
+pub struct LowerBound;
+pub struct UpperBound;
+pub struct Predecessor;
+pub struct EqualRange;
+pub struct RangeCount;
+
+impl QueryKind for LowerBound {
+    const KIND: Kind = Kind::LowerBound;
+}
+
+impl QueryKind for UpperBound {
+    const KIND: Kind = Kind::UpperBound;
+}
+
+impl QueryKind for Predecessor {
+    const KIND: Kind = Kind::Predecessor;
+}
+
+impl QueryKind for EqualRange {
+    const KIND: Kind = Kind::EqualRange;
+}
+
+impl QueryKind for RangeCount {
+    const KIND: Kind = Kind::RangeCount;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{query::searchable::Searchable, searches::{b_eytzinger::BEytzinger, binary_search::SortedVec, dynamic::DynamicSTree, eytzinger::Eytzinger, prefix_lut::PrefixLut, ranked::RANGE_WIDTH, s_tree::{STree, MAX}, s_tree_compressed::CompressedSTree, veb_tree::VebTree}};
+
+    use super::*;
+
+    fn expected<K: QueryKind>(vals: &[u32], q: u32, sentinel: u32) -> u32 {
+        let lower = vals.partition_point(|&x| x < q);
+        let upper = vals.partition_point(|&x| x <= q);
+        let key = |rank: usize| vals.get(rank).copied().unwrap_or(sentinel);
+        match K::KIND {
+            Kind::LowerBound => key(lower),
+            Kind::UpperBound => key(upper),
+            Kind::Predecessor => upper.checked_sub(1).map_or(sentinel, key),
+            Kind::EqualRange => (upper - lower) as u32,
+            Kind::RangeCount => (q.checked_add(RANGE_WIDTH).map_or(vals.len(), |hi| vals.partition_point(|&x| x < hi)) - lower) as u32,
+        }
+    }
+
+    // Miri is far too slow for the full sweep, so it only checks the sizes around node boundaries.
+    #[cfg(not(miri))]
+    fn lens() -> impl Iterator<Item = u32> {
+        (0..200).chain([4912, 4913, 4914])
+    }
+
+    #[cfg(miri)]
+    fn lens() -> impl Iterator<Item = u32> {
+        [0, 1, 16, 17, 273].into_iter()
+    }
+
+    fn check_keys<S: Searchable + 'static, K: QueryKind>(vals: &[u32], mut queries: Vec<u32>, sentinel: u32) {
+        let s = S::new(vals);
+        queries.resize(queries.len().next_multiple_of(128), 0);
+        let expected: Vec<u32> = queries.iter().map(|&q| expected::<K>(vals, q, sentinel)).collect();
+        for func in S::all_funcs::<K>() {
+            assert_eq!(func.query(&s, &queries), expected, "len {}, {}", vals.len(), func.get_name());
+        }
+    }
+
+    fn check<S: Searchable + 'static, K: QueryKind>(sentinel: u32) {
+        for len in lens() {
+            let vals: Vec<u32> = (0..len).map(|i| i / 3 * 2 + 1).collect();
+            check_keys::<S, K>(&vals, (0..len + 3).collect(), sentinel);
+        }
+    }
+
+    /// Keys up to `u32::MAX - 1`, the largest the structures that take the whole range allow.
+    fn check_top<S: Searchable + 'static, K: QueryKind>() {
+        let top = u32::MAX - 1;
+        let near_top: Vec<u32> = (0..300).map(|i| top - 600 + 2 * i).chain([top]).collect();
+        for vals in [vec!(top), vec!(1, top), vec!(top - 1, top, top), near_top] {
+            let queries = [0, 1, 2, u32::MAX - RANGE_WIDTH].into_iter().chain(u32::MAX - 700..=u32::MAX).collect();
+            check_keys::<S, K>(&vals, queries, u32::MAX);
+        }
+    }
+
+    fn check_all<S: Searchable + 'static>(sentinel: u32) {
+        check::<S, LowerBound>(sentinel);
+        check::<S, UpperBound>(sentinel);
+        check::<S, Predecessor>(sentinel);
+        check::<S, EqualRange>(sentinel);
+        check::<S, RangeCount>(sentinel);
+    }
+
+    fn check_all_top<S: Searchable + 'static>() {
+        check_top::<S, LowerBound>();
+        check_top::<S, UpperBound>();
+        check_top::<S, Predecessor>();
+        check_top::<S, EqualRange>();
+        check_top::<S, RangeCount>();
+    }
+
+    #[test]
+    fn test_sorted_vec() {
+        check_all::<SortedVec>(u32::MAX);
+    }
+
+    #[test]
+    fn test_eytzinger() {
+        check_all::<Eytzinger>(u32::MAX);
+    }
+
+    #[test]
+    fn test_s_tree() {
+        check_all::<STree>(MAX);
+    }
+
+    #[test]
+    fn test_compressed_s_tree() {
+        check_all::<CompressedSTree>(MAX);
+    }
+
+    #[test]
+    fn test_others() {
+        check_all::<VebTree>(u32::MAX);
+        check_all::<BEytzinger>(MAX);
+        check_all::<PrefixLut<STree, 8>>(MAX);
+        check_all::<DynamicSTree<64>>(MAX);
+    }
+
+    #[test]
+    fn test_largest_keys() {
+        check_all_top::<SortedVec>();
+        check_all_top::<Eytzinger>();
+        check_all_top::<VebTree>();
+    }
+
+    fn rejects<S: Searchable>(key: u32) -> bool {
+        std::panic::catch_unwind(|| S::new(&[1, key])).is_err()
+    }
+
+    #[test]
+    fn test_rejects_keys_out_of_range() {
+        assert!(rejects::<SortedVec>(u32::MAX));
+        assert!(rejects::<Eytzinger>(u32::MAX));
+        assert!(rejects::<VebTree>(u32::MAX));
+        for key in [MAX + 1, 3_000_000_000, u32::MAX] {
+            assert!(rejects::<STree>(key));
+            assert!(rejects::<CompressedSTree>(key));
+            assert!(rejects::<BEytzinger>(key));
+            assert!(rejects::<PrefixLut<STree, 8>>(key));
+            assert!(rejects::<DynamicSTree<64>>(key));
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::marker::PhantomData;
+
+use super::query_kind::QueryKind;
+
+pub trait Searchable: Sized{
+    fn new(sorted_vals: &[u32]) -> Self;
+    /// The schemes the benchmark measures by default.
+    fn get_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>>;
+    /// Every scheme, including the ones `get_funcs` leaves out, for experiments to pick from.
+    fn all_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        Self::get_funcs::<K>()
+    }
+    fn get_name(&self) -> String{
+        std::any::type_name::<Self>().to_string()
+    }
+}
+
+/// A way to answer queries on an `I`. Schemes are `Sync`, so the benchmark can share one
+/// between threads.
+pub trait SearchScheme<I: Searchable>: Sync {
+    fn query(&self, searchable: &I, values: &[u32]) -> Vec<u32>{
+        values.iter().copied().map(|val| self.query_one(searchable, val)).collect()
+    }
+
+    fn query_one(&self, searchable: &I, value: u32) -> u32 {
+        self.query(searchable, &[value])[0]
+    }
+
+    /// How many queries the scheme answers at once. A single query still costs a whole batch.
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    fn get_name(&self) -> String{
+        std::any::type_name::<Self>().to_string()
+    }
+}
+
+impl <I: Searchable, F: Fn(&I, u32) -> u32 + Sync> SearchScheme<I> for F {
+    fn query_one(&self, searchable: &I, value: u32) -> u32 {
+        self(searchable, value)
+    }
+}
+
+pub struct Batched<const P: usize, I: Searchable, F: for<'a> Fn(&'a I, &[u32; P]) -> [u32; P]>(
+    F,
+    PhantomData<fn(&I)>,
+);
+
+
+pub const fn batched<const P: usize, I: Searchable, F: for<'a> Fn(&'a I, &[u32; P]) -> [u32; P]>(
+    f: F
+) -> Batched<P, I, F>{
+    Batched(f, PhantomData)
+}
+
+impl<const P: usize, I: Searchable, F: for<'a> Fn(&'a I, &[u32; P]) -> [u32; P] + Sync> SearchScheme<I> for Batched<P, I, F> {
+    /// A last partial batch is padded with copies of its last value, and only its own
+    /// answers are kept.
+    fn query(&self, searchable: &I, values: &[u32]) -> Vec<u32> {
+        let (chunks, remainder) = values.as_chunks();
+        let mut results: Vec<u32> = chunks.iter().flat_map(|val| (self.0)(searchable, val)).collect();
+        if let Some(&last) = remainder.last() {
+            let mut padded = [last; P];
+            padded[..remainder.len()].copy_from_slice(remainder);
+            results.extend_from_slice(&(self.0)(searchable, &padded)[..remainder.len()]);
+        }
+        results
+    }
+
+    fn batch_size(&self) -> usize {
+        P
+    }
+}
@@ -0,0 +1,220 @@
+use std::hint::select_unpredictable;
+
+use crate::{query::{searchable::{batched, SearchScheme, Searchable}, query_kind::{Kind, QueryKind}}, utils::prefetch_index};
+
+use super::{from_sorted::FromSorted, ranked::{answer, Ranked}, s_tree::{STree, MAX, NODE_LEN}, s_tree_node::STreeNode};
+
+/// Implicit B-ary search tree in Eytzinger order.
+///
+/// Node `k` has its children at `k * (NODE_LEN + 1) + i + 1`, so unlike `STree` there is
+/// no per-layer offset table to consult while descending. As in `STree`, keys must be at
+/// most `MAX`.
+#[derive(Debug)]
+pub struct BEytzinger {
+    nodes: Vec<STreeNode>,
+    height: usize,
+    len: usize,
+}
+
+impl BEytzinger {
+    fn first_node(h: usize) -> usize {
+        ((NODE_LEN + 1).pow(h as u32) - 1) / NODE_LEN
+    }
+
+    /// Number of nodes in the subtrees rooted at `lo..hi`, which are siblings.
+    fn nodes_in(&self, mut lo: usize, mut hi: usize) -> usize {
+        let mut count = 0;
+        while lo < self.nodes.len() && lo < hi {
+            count += hi.min(self.nodes.len()) - lo;
+            (lo, hi) = (lo * (NODE_LEN + 1) + 1, hi * (NODE_LEN + 1) + 1);
+        }
+        count
+    }
+
+    /// Number of keys, padding included, in the subtrees of the first `c` children of node `k`.
+    fn keys_left_of(&self, k: usize, c: usize) -> usize {
+        let first_child = k * (NODE_LEN + 1) + 1;
+        NODE_LEN * self.nodes_in(first_child, first_child + c)
+    }
+}
+
+impl Searchable for BEytzinger {
+    fn new(sorted_vals: &[u32]) -> Self {
+        assert!(sorted_vals.last().is_none_or(|&key| key <= MAX), "Keys must be at most MAX");
+        Self::from_exact(sorted_vals.len(), sorted_vals.iter().copied())
+    }
+
+    fn get_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        // The searches only track keys; counting goes through the ranks of `Ranked`.
+        if K::KIND.needs_rank() {
+            return vec!(&Self::search_ranked::<K>);
+        }
+        let batch_128 = Box::leak(Box::new(batched(Self::batch::<128, K>)));
+        let batch_128_prefetch = Box::leak(Box::new(batched(Self::batch_prefetch::<128, K>)));
+        vec!(&Self::search::<K>, batch_128, batch_128_prefetch)
+    }
+}
+
+impl FromSorted for BEytzinger {
+    const MAX_KEY: u32 = MAX;
+
+    fn from_exact(len: usize, mut keys: impl Iterator<Item = u32>) -> Self {
+        let n_blocks = STree::blocks_needed(len);
+        let mut nodes = vec![STreeNode{keys: [MAX; NODE_LEN]}; n_blocks];
+
+        fn recurse(nodes: &mut Vec<STreeNode>, keys: &mut impl Iterator<Item = u32>, k: usize) {
+            if k < nodes.len() {
+                for j in 0..NODE_LEN {
+                    recurse(nodes, keys, k * (NODE_LEN + 1) + j + 1);
+                    nodes[k].keys[j] = keys.next().unwrap_or(MAX);
+                }
+                recurse(nodes, keys, k * (NODE_LEN + 1) + NODE_LEN + 1);
+            }
+        }
+
+        recurse(&mut nodes, &mut keys.take(len), 0);
+        let height = (0..).find(|&h| Self::first_node(h) >= n_blocks).unwrap();
+        Self { nodes, height, len }
+    }
+}
+
+impl BEytzinger {
+    fn node(&self, node_idx: usize) -> &STreeNode {
+        debug_assert!(node_idx < self.nodes.len(), "node {node_idx} out of bounds");
+        // SAFETY: every search checks `k < self.nodes.len()` before reading node `k`.
+        unsafe { self.nodes.get_unchecked(node_idx) }
+    }
+
+    fn key(&self, node_idx: usize, key_idx: usize) -> u32 {
+        self.node(node_idx).keys[key_idx % NODE_LEN]
+    }
+
+    /// The key of node `k` that answers the query if the search ends below it, and whether it exists.
+    ///
+    /// That is the first key `>= value` for successor queries, or the last one before it for
+    /// predecessor queries.
+    #[inline(always)]
+    fn candidate<K: QueryKind>(&self, k: usize, jump_to: usize) -> (bool, u32) {
+        if K::KIND == Kind::Predecessor {
+            (jump_to > 0, self.key(k, jump_to.saturating_sub(1)))
+        } else {
+            (jump_to < NODE_LEN, self.key(k, jump_to.min(NODE_LEN - 1)))
+        }
+    }
+
+    #[inline(never)]
+    fn search<K: QueryKind>(&self, value: u32) -> u32 {
+        let value = K::KIND.target(value).min(MAX);
+        let mut k = 0;
+        let mut ans = MAX;
+        while k < self.nodes.len() {
+            let jump_to = self.node(k).find_popcnt(value);
+            let (found, candidate) = self.candidate::<K>(k, jump_to);
+            ans = select_unpredictable(found, candidate, ans);
+            k = k * (NODE_LEN + 1) + jump_to + 1;
+        }
+        ans
+    }
+
+    #[inline(never)]
+    fn search_ranked<K: QueryKind>(&self, q: u32) -> u32 {
+        answer::<K, _>(self, q, self.lower_bound(K::KIND.target(q)))
+    }
+
+    #[inline(always)]
+    fn batch_impl<const P: usize, K: QueryKind, const PREFETCH: bool>(&self, values: &[u32; P]) -> [u32; P] {
+        let values = values.map(|v| K::KIND.target(v).min(MAX));
+        let n = self.nodes.len();
+        let mut k = [0; P];
+        let mut ans = [MAX; P];
+        for _ in 0..self.height {
+            for i in 0..P {
+                let in_bounds = k[i] < n;
+                let node_idx = select_unpredictable(in_bounds, k[i], 0);
+                let jump_to = self.node(node_idx).find_popcnt(values[i]);
+                let (found, candidate) = self.candidate::<K>(node_idx, jump_to);
+                ans[i] = select_unpredictable(in_bounds && found, candidate, ans[i]);
+                k[i] = select_unpredictable(in_bounds, k[i] * (NODE_LEN + 1) + jump_to + 1, k[i]);
+                if PREFETCH && k[i] < n {
+                    prefetch_index(&self.nodes, k[i]);
+                }
+            }
+        }
+        ans
+    }
+
+    #[inline(never)]
+    fn batch<const P: usize, K: QueryKind>(&self, values: &[u32; P]) -> [u32; P] {
+        self.batch_impl::<P, K, false>(values)
+    }
+
+    #[inline(never)]
+    fn batch_prefetch<const P: usize, K: QueryKind>(&self, values: &[u32; P]) -> [u32; P] {
+        self.batch_impl::<P, K, true>(values)
+    }
+}
+
+/// Ranks are in-order positions. The padding keys come after all real keys, so they only
+/// occupy ranks `>= len`.
+impl Ranked for BEytzinger {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn key_at(&self, mut rank: usize) -> u32 {
+        if rank >= self.len {
+            return MAX;
+        }
+        let mut k = 0;
+        loop {
+            let mut c = 0;
+            // Find the child whose subtree, or the key after it, holds the rank.
+            while c < NODE_LEN && self.keys_left_of(k, c + 1) + c < rank {
+                c += 1;
+            }
+            let left = self.keys_left_of(k, c) + c;
+            let in_child = self.keys_left_of(k, c + 1) + c;
+            if rank == in_child {
+                return self.key(k, c);
+            }
+            rank -= left;
+            k = k * (NODE_LEN + 1) + c + 1;
+        }
+    }
+
+    fn lower_bound(&self, value: u32) -> usize {
+        let mut k = 0;
+        let mut rank = 0;
+        while k < self.nodes.len() {
+            let jump_to = self.node(k).find_popcnt(value.min(MAX));
+            rank += self.keys_left_of(k, jump_to) + jump_to;
+            k = k * (NODE_LEN + 1) + jump_to + 1;
+        }
+        rank.min(self.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::query_kind::LowerBound;
+
+    use super::*;
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_matches_partition_point() {
+        for len in (0..600).chain([4912, 4913, 4914]) {
+            let vals: Vec<u32> = (0..len).map(|i| 3 * i).collect();
+            let tree = BEytzinger::new(&vals);
+            let mut queries: Vec<u32> = (0..3 * len + 2).collect();
+            queries.resize(queries.len().next_multiple_of(128), 0);
+            let expected: Vec<u32> = queries.iter()
+                .map(|&q| vals.get(vals.partition_point(|&x| x < q)).copied().unwrap_or(MAX))
+                .collect();
+
+            for func in BEytzinger::get_funcs::<LowerBound>() {
+                assert_eq!(func.query(&tree, &queries), expected, "len {len}, {}", func.get_name());
+            }
+        }
+    }
+}
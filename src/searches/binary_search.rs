@@ -1,96 +1,173 @@
-use std::{hint::black_box, intrinsics::{prefetch_read_data, select_unpredictable}};
+use std::hint::{black_box, select_unpredictable};
 
-use crate::{query::bench_search::{SearchScheme, Searchable}, utils::prefetch_index};
+use crate::{query::{searchable::{SearchScheme, Searchable}, query_kind::QueryKind}, utils::prefetch_index};
 
+use super::{from_sorted::FromSorted, persist::Persist, prefix_lut::SubrangeSearch, storage::Storage, ranked::{answer, Ranked}};
+
+/// The keys in a sorted array. Keys must be below `u32::MAX`, which searches report when
+/// there is no answer.
+#[derive(Debug)]
 #[repr(align(64))]
 pub struct SortedVec{
-    pub vals: Vec<u32>,
+    vals: Storage<u32>,
 }
 
 impl Searchable for SortedVec{
     fn new(sorted_vals: &[u32]) -> Self {
-        SortedVec{vals: sorted_vals.to_vec()}
+        assert!(sorted_vals.last().is_none_or(|&key| key < u32::MAX), "Keys must be below u32::MAX");
+        SortedVec{vals: sorted_vals.to_vec().into()}
+    }
+
+    fn get_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        vec!(&Self::binary_search_branchless_prefetching::<K>)
+    }
+
+    fn all_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        vec!(
+            &Self::binary_search_normal::<K>,
+            &Self::binary_search_branchless_prefetching::<K>,
+            &Self::binary_search_branchless::<K>,
+            &Self::std_binary_search::<K>,
+            &Self::binary_search_random::<K>,
+        )
+    }
+}
+
+impl FromSorted for SortedVec{
+    const MAX_KEY: u32 = u32::MAX - 1;
+
+    fn from_exact(len: usize, keys: impl Iterator<Item = u32>) -> Self {
+        let mut vals = Vec::with_capacity(len);
+        vals.extend(keys.take(len));
+        SortedVec{vals: vals.into()}
+    }
+}
+
+impl Ranked for SortedVec{
+    fn len(&self) -> usize {
+        self.vals.len()
+    }
+
+    fn key_at(&self, rank: usize) -> u32 {
+        self.vals.get(rank).copied().unwrap_or(u32::MAX)
+    }
+
+    fn lower_bound(&self, value: u32) -> usize {
+        self.lower_bound_in(value, 0, self.vals.len())
+    }
+}
+
+impl Persist for SortedVec{
+    const STRUCTURE: u32 = 0;
+    const NODE_WIDTH: u32 = 1;
+    type Node = u32;
+
+    fn parts(&self) -> (&[usize], &[u32]) {
+        (&[], &self.vals)
     }
 
-    fn get_funcs() -> Vec<&'static dyn SearchScheme<Self>> {
-        //&[&Self::binary_search_normal, &Self::binary_search_branchless_prefetching, &Self::binary_search_branchless, &Self::std_binary_search, &Self::binary_search_random]
-        vec!(&Self::binary_search_branchless_prefetching)
+    fn from_parts(len: usize, offsets: Vec<usize>, vals: Storage<u32>) -> Option<Self> {
+        (offsets.is_empty() && vals.len() == len).then_some(Self { vals })
+    }
+}
+
+impl SubrangeSearch for SortedVec{
+    fn lower_bound_in(&self, value: u32, lo: usize, hi: usize) -> usize {
+        assert!(lo <= hi && hi <= self.len(), "Subrange {lo}..{hi} out of bounds for {} keys", self.len());
+        let mut base = lo;
+        let mut len = hi - lo;
+
+        while len > 1 {
+            let half = len / 2;
+            let cmp = self.get(base + half - 1) < value;
+            base = select_unpredictable(cmp, base + half, base);
+            len -= half;
+        }
+
+        base + (len == 1 && self.get(base) < value) as usize
     }
 }
 
 impl SortedVec{
-    pub fn get(&self, index: usize) -> u32 {
+    fn get(&self, index: usize) -> u32 {
+        debug_assert!(index < self.vals.len(), "index {index} out of bounds");
+        // SAFETY: the searches only read `base + half - 1 < base + len`, or `base` when
+        // `len == 1`, and keep `base + len <= self.vals.len()`.
         unsafe { *self.vals.get_unchecked(index) }
     }
 
     #[inline(never)]
-    fn std_binary_search(&self, num: u32) -> u32{
-        let idx = self.vals.binary_search(&num).unwrap_or_else(|i| i);
-        self.get(idx)
+    fn std_binary_search<K: QueryKind>(&self, num: u32) -> u32{
+        let target = K::KIND.target(num);
+        let idx = self.vals.partition_point(|&x| x < target);
+        answer::<K, _>(self, num, idx)
     }
 
     #[inline(never)]
-    fn binary_search_random(&self, num: u32) -> u32{
+    fn binary_search_random<K: QueryKind>(&self, num: u32) -> u32{
+        let target = K::KIND.target(num);
         let mut l = 0;
-        let mut r = self.vals.len() - 1;
+        let mut r = self.vals.len().saturating_sub(1);
         while l < r {
             let ran = rand::random_range(0..=10_000_000);
             let m = l + ran % (r - l);
-            if self.vals[m] >= num{
+            if self.vals[m] >= target{
                 r = m;
             } else {
                 l = m + 1;
             }
         }
-        black_box(self.get(l))
+        black_box(answer::<K, _>(self, num, l + self.vals.get(l).is_some_and(|&x| x < target) as usize))
     }
 
     #[inline(never)]
-    fn binary_search_normal(&self, num: u32) -> u32{
+    fn binary_search_normal<K: QueryKind>(&self, num: u32) -> u32{
+        let target = K::KIND.target(num);
         let mut l = 0;
-        let mut r = self.vals.len() - 1;
+        let mut r = self.vals.len().saturating_sub(1);
         while l < r{
             let m = (l + r) / 2;
-            if self.vals[m] >= num{
+            if self.vals[m] >= target{
                 r = m;
             }
             else {
                 l = m + 1;
             }
         }
-        black_box(self.get(l))
+        black_box(answer::<K, _>(self, num, l + self.vals.get(l).is_some_and(|&x| x < target) as usize))
     }
 
     #[inline(never)]
-    fn binary_search_branchless_prefetching(&self, num: u32) -> u32{
+    fn binary_search_branchless_prefetching<K: QueryKind>(&self, num: u32) -> u32{
+        let target = K::KIND.target(num);
         let mut base = 0;
         let mut len = self.vals.len();
 
         while len > 1 {
             let half = len / 2;
-            prefetch_index(&self.vals, base + half / 2 - 1);
-            prefetch_index(&self.vals, base + half + half / 2 - 1);
-            let cmp = self.get(base + half - 1) < num;
-            base = cmp.select_unpredictable(base + half, base);
+            prefetch_index(&self.vals, base + (half / 2).saturating_sub(1));
+            prefetch_index(&self.vals, base + half + (half / 2).saturating_sub(1));
+            let cmp = self.get(base + half - 1) < target;
+            base = select_unpredictable(cmp, base + half, base);
             len -= half;
         }
 
-        black_box(self.get(base))
+        black_box(answer::<K, _>(self, num, base + (len == 1 && self.get(base) < target) as usize))
     }
 
     #[inline(never)]
-    fn binary_search_branchless(&self, num: u32) -> u32{
+    fn binary_search_branchless<K: QueryKind>(&self, num: u32) -> u32{
+        let target = K::KIND.target(num);
         let mut base = 0;
         let mut len = self.vals.len();
 
         while len > 1 {
             let half = len / 2;
-            let cmp = self.get(base + half - 1) < num;
-            base = cmp.select_unpredictable(base + half, base);
+            let cmp = self.get(base + half - 1) < target;
+            base = select_unpredictable(cmp, base + half, base);
             len -= half;
         }
 
-        black_box(self.get(base))
+        black_box(answer::<K, _>(self, num, base + (len == 1 && self.get(base) < target) as usize))
     }
 }
-
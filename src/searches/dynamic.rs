@@ -0,0 +1,271 @@
+use std::array::from_fn;
+
+use crate::query::{searchable::{batched, SearchScheme, Searchable}, query_kind::{Kind, QueryKind}};
+
+use super::{from_sorted::FromSorted, ranked::{answer, Ranked}, s_tree::{STree, MAX}};
+
+/// An `STree` that can be updated, by keeping the changes since it was built in a small
+/// sorted delta.
+///
+/// Inserted keys go to a sorted buffer, deleted keys of the tree become tombstones. Queries
+/// combine the answer of the tree with the delta, and [`DynamicSTree::rebuild`] folds the
+/// delta into a new tree. Keys are a multiset, and like in `STree` they must be at most `MAX`.
+///
+/// `DELTA` only matters for [`Searchable::new`], which moves that many keys into the delta
+/// so that benchmarks see a delta of that size.
+#[derive(Debug)]
+pub struct DynamicSTree<const DELTA: usize = 0> {
+    base: STree,
+    inserts: Vec<u32>,
+    // Each tombstone removes one copy of its key from `base`.
+    tombstones: Vec<u32>,
+}
+
+impl<const DELTA: usize> DynamicSTree<DELTA> {
+    /// Number of inserts and tombstones waiting for the next rebuild.
+    pub fn delta_len(&self) -> usize {
+        self.inserts.len() + self.tombstones.len()
+    }
+
+    pub fn insert(&mut self, key: u32) {
+        assert!(key <= MAX, "Keys must be at most MAX");
+        let idx = self.inserts.partition_point(|&x| x <= key);
+        self.inserts.insert(idx, key);
+    }
+
+    /// Removes one copy of `key`, and returns whether there was one.
+    pub fn delete(&mut self, key: u32) -> bool {
+        if let Ok(idx) = self.inserts.binary_search(&key) {
+            self.inserts.remove(idx);
+            return true;
+        }
+        let dead = self.tombstones.partition_point(|&x| x < key)..self.tombstones.partition_point(|&x| x <= key);
+        if self.base.equal_range(key).len() > dead.len() {
+            self.tombstones.insert(dead.end, key);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Builds a new tree with the delta folded in.
+    pub fn rebuild(&mut self) {
+        let base = STree::from_exact(self.len(), self.merged(0, u32::MAX));
+        *self = Self { base, inserts: Vec::new(), tombstones: Vec::new() };
+    }
+
+    /// All keys in `[lo, hi)` in sorted order, read from the tree and the delta at once.
+    fn merged(&self, lo: u32, hi: u32) -> impl Iterator<Item = u32> + '_ {
+        let mut base = self.base.range(lo, hi).peekable();
+        let mut inserts = self.inserts[self.inserts.partition_point(|&x| x < lo)..].iter().copied().peekable();
+        let mut tombstones = self.tombstones[self.tombstones.partition_point(|&x| x < lo)..].iter().copied().peekable();
+        std::iter::from_fn(move || loop {
+            let from_base = match (base.peek(), inserts.peek()) {
+                (Some(&b), Some(&i)) => b <= i,
+                (Some(_), None) => true,
+                (None, Some(&i)) => return (i < hi).then(|| inserts.next().unwrap()),
+                (None, None) => return None,
+            };
+            if !from_base {
+                return inserts.next();
+            }
+            let key = base.next().unwrap();
+            if tombstones.next_if_eq(&key).is_none() {
+                return Some(key);
+            }
+        })
+    }
+
+    /// The first live key `>= target`, given the rank of the lower bound of `target` in the tree.
+    #[inline(always)]
+    fn successor(&self, target: u32, mut rank: usize) -> u32 {
+        let mut dead = self.tombstones.partition_point(|&x| x < target);
+        // Skip keys of the tree whose copies have all been deleted.
+        while rank < self.base.len() && self.tombstones.get(dead) == Some(&self.base.key_at(rank)) {
+            let key = self.base.key_at(rank);
+            let copies = self.base.upper_bound(key) - rank;
+            let deleted = Self::count_from(&self.tombstones, dead, key);
+            if copies > deleted {
+                break;
+            }
+            rank += copies;
+            dead += deleted;
+        }
+        let from_base = self.base.key_at(rank);
+        let from_inserts = self.inserts.get(self.inserts.partition_point(|&x| x < target)).copied().unwrap_or(MAX);
+        from_base.min(from_inserts)
+    }
+
+    /// The last live key `< target`, given the rank of the lower bound of `target` in the tree.
+    #[inline(always)]
+    fn predecessor(&self, target: u32, mut rank: usize) -> Option<u32> {
+        let mut dead = self.tombstones.partition_point(|&x| x < target);
+        while rank > 0 && dead > 0 && self.tombstones[dead - 1] == self.base.key_at(rank - 1) {
+            let key = self.base.key_at(rank - 1);
+            let copies = rank - self.base.lower_bound(key);
+            let deleted = dead - self.tombstones[..dead].partition_point(|&x| x < key);
+            if copies > deleted {
+                break;
+            }
+            rank -= copies;
+            dead -= deleted;
+        }
+        let from_base = rank.checked_sub(1).map(|rank| self.base.key_at(rank));
+        let from_inserts = self.inserts.partition_point(|&x| x < target).checked_sub(1).map(|idx| self.inserts[idx]);
+        from_base.max(from_inserts)
+    }
+
+    fn count_from(sorted: &[u32], start: usize, key: u32) -> usize {
+        sorted[start..].partition_point(|&x| x <= key)
+    }
+
+    /// Answers query `value` given the rank of the lower bound of its target in the tree.
+    #[inline(always)]
+    fn finish<K: QueryKind>(&self, value: u32, base_rank: usize) -> u32 {
+        let target = K::KIND.target(value);
+        match K::KIND {
+            Kind::LowerBound | Kind::UpperBound => self.successor(target, base_rank),
+            Kind::Predecessor => self.predecessor(target, base_rank).unwrap_or(MAX),
+            Kind::EqualRange | Kind::RangeCount => {
+                let rank = base_rank + self.inserts.partition_point(|&x| x < target)
+                    - self.tombstones.partition_point(|&x| x < target);
+                answer::<K, _>(self, value, rank)
+            }
+        }
+    }
+
+    #[inline(never)]
+    fn search<K: QueryKind>(&self, value: u32) -> u32 {
+        self.finish::<K>(value, self.base.lower_bound(K::KIND.target(value)))
+    }
+
+    #[inline(never)]
+    fn batch_prefetch<const P: usize, K: QueryKind>(&self, values: &[u32; P]) -> [u32; P] {
+        let ranks = self.base.batch_lower_bound(&values.map(|v| K::KIND.target(v)));
+        from_fn(|i| self.finish::<K>(values[i], ranks[i]))
+    }
+}
+
+impl<const DELTA: usize> Searchable for DynamicSTree<DELTA> {
+    fn new(sorted_vals: &[u32]) -> Self {
+        assert!(sorted_vals.last().is_none_or(|&key| key <= MAX), "Keys must be at most MAX");
+        // Take evenly spread keys out of the tree and insert them again, and add copies of
+        // others to the tree only to delete them again. The keys stay the same.
+        let len = sorted_vals.len();
+        let sample = |n: usize| -> Vec<usize> { (0..n.min(len)).map(|j| j * len / n.min(len)).collect() };
+        let moved = sample(DELTA / 2);
+        let doubled = sample(DELTA - DELTA / 2);
+
+        let mut base = Vec::with_capacity(len + doubled.len());
+        let (mut m, mut d) = (0, 0);
+        for (i, &val) in sorted_vals.iter().enumerate() {
+            if moved.get(m) == Some(&i) {
+                m += 1;
+            } else {
+                base.push(val);
+            }
+            if doubled.get(d) == Some(&i) {
+                d += 1;
+                base.push(val);
+            }
+        }
+
+        Self {
+            base: STree::new(&base),
+            inserts: moved.iter().map(|&i| sorted_vals[i]).collect(),
+            tombstones: doubled.iter().map(|&i| sorted_vals[i]).collect(),
+        }
+    }
+
+    fn get_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        let batch_128_prefetch = Box::leak(Box::new(batched(Self::batch_prefetch::<128, K>)));
+        vec!(&Self::search::<K>, batch_128_prefetch)
+    }
+}
+
+impl<const DELTA: usize> Ranked for DynamicSTree<DELTA> {
+    fn len(&self) -> usize {
+        self.base.len() + self.inserts.len() - self.tombstones.len()
+    }
+
+    /// Searches the key space for the key, so this takes 32 lower bound queries.
+    fn key_at(&self, rank: usize) -> u32 {
+        if rank >= self.len() {
+            return MAX;
+        }
+        // The largest key whose lower bound is at most `rank`.
+        let (mut lo, mut hi) = (0u32, MAX);
+        while lo < hi {
+            let mid = lo + (hi - lo).div_ceil(2);
+            if self.lower_bound(mid) <= rank {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        lo
+    }
+
+    fn lower_bound(&self, value: u32) -> usize {
+        self.base.lower_bound(value) + self.inserts.partition_point(|&x| x < value)
+            - self.tombstones.partition_point(|&x| x < value)
+    }
+
+    fn range(&self, lo: u32, hi: u32) -> impl Iterator<Item = u32> + '_ {
+        self.merged(lo, hi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn check(tree: &DynamicSTree, reference: &[u32]) {
+        assert_eq!(tree.len(), reference.len());
+        assert_eq!(tree.range(0, u32::MAX).collect::<Vec<_>>(), reference);
+        for q in (0..250).chain([MAX]) {
+            let lower = reference.partition_point(|&x| x < q);
+            let upper = reference.partition_point(|&x| x <= q);
+            assert_eq!(tree.lower_bound(q), lower, "query {q}");
+            assert_eq!(tree.search::<crate::LowerBound>(q), reference.get(lower).copied().unwrap_or(MAX), "query {q}");
+            assert_eq!(tree.search::<crate::UpperBound>(q), reference.get(upper).copied().unwrap_or(MAX), "query {q}");
+            let pred = upper.checked_sub(1).map_or(MAX, |rank| reference[rank]);
+            assert_eq!(tree.search::<crate::Predecessor>(q), pred, "query {q}");
+            assert_eq!(tree.search::<crate::EqualRange>(q), (upper - lower) as u32, "query {q}");
+        }
+        for (rank, &key) in reference.iter().enumerate() {
+            assert_eq!(tree.key_at(rank), key, "rank {rank}");
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_matches_reference() {
+        let mut rng = StdRng::seed_from_u64(36);
+        for len in [0, 1, 17, 300] {
+            let mut reference: Vec<u32> = (0..len).map(|_| rng.random_range(0..200)).collect();
+            reference.sort();
+            let mut tree = DynamicSTree::new(&reference);
+            for round in 0..400 {
+                let key = rng.random_range(0..200);
+                if rng.random_bool(0.5) {
+                    tree.insert(key);
+                    reference.insert(reference.partition_point(|&x| x <= key), key);
+                } else {
+                    let found = reference.binary_search(&key).map(|idx| reference.remove(idx)).is_ok();
+                    assert_eq!(tree.delete(key), found, "delete {key}");
+                }
+                if round % 50 == 0 {
+                    check(&tree, &reference);
+                }
+                if round % 150 == 149 {
+                    tree.rebuild();
+                    assert_eq!(tree.delta_len(), 0);
+                }
+            }
+            check(&tree, &reference);
+        }
+    }
+}
@@ -1,126 +1,281 @@
 use cmov::Cmov;
 
-use crate::{query::bench_search::{SearchScheme, Searchable}, utils::prefetch_index};
+use crate::{query::{searchable::{SearchScheme, Searchable}, query_kind::{Kind, QueryKind}}, utils::prefetch_index};
+
+use super::{from_sorted::FromSorted, persist::Persist, ranked::{answer, Ranked}, storage::Storage};
 
 fn search_result_to_index(idx: usize) -> usize {
     idx >> (idx.trailing_ones() + 1)
 }
 
+/// The keys in BFS order of a complete binary tree. Keys must be below `u32::MAX`, which
+/// pads the tree and which searches report when there is no answer.
+#[derive(Debug)]
 #[repr(align(64))]
 pub struct Eytzinger {
-    vals: Vec<u32>,
+    vals: Storage<u32>,
     num_iters: usize, 
 }
 
 impl Searchable for Eytzinger{
     fn new(sorted_vals: &[u32]) -> Self {
-        let len = sorted_vals.len() + 1;
-        let mut eytz_vec = vec![0; len];
-        eytz_vec[0] = u32::MAX;
-
-        fn recurse(eytz_vec: &mut Vec<u32>, sorted_vals: &[u32], k: usize, i: &mut usize) {
-            if k <= sorted_vals.len(){
-                recurse(eytz_vec, sorted_vals, k * 2, i);
-                eytz_vec[k] = sorted_vals[*i];
-                *i += 1;
-                recurse(eytz_vec, sorted_vals, 2 * k + 1, i);
+        assert!(sorted_vals.last().is_none_or(|&key| key < u32::MAX), "Keys must be below u32::MAX");
+        Self::from_exact(sorted_vals.len(), sorted_vals.iter().copied())
+    }
+
+    fn get_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        vec!(&Eytzinger::search_prefetch::<K>)
+    }
+
+    fn all_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        vec!(
+            &Eytzinger::eyz_search::<K>,
+            &Eytzinger::search_prefetch::<K>,
+            &Eytzinger::search_branchless::<K>,
+            &Eytzinger::search_branchless_prefetch::<K>,
+        )
+    }
+
+}
+
+impl FromSorted for Eytzinger{
+    const MAX_KEY: u32 = u32::MAX - 1;
+
+    fn from_exact(len: usize, mut keys: impl Iterator<Item = u32>) -> Self {
+        let mut eytz_vec = vec![u32::MAX; len + 1];
+
+        // An in-order walk of the tree visits the nodes in key order, so it can take the
+        // keys as they come.
+        fn recurse(eytz_vec: &mut Vec<u32>, keys: &mut impl Iterator<Item = u32>, k: usize) {
+            if k < eytz_vec.len(){
+                recurse(eytz_vec, keys, k * 2);
+                eytz_vec[k] = keys.next().unwrap_or(u32::MAX);
+                recurse(eytz_vec, keys, 2 * k + 1);
             }
         }
 
-        recurse(&mut eytz_vec, sorted_vals, 1, &mut 0);
+        recurse(&mut eytz_vec, &mut keys, 1);
         Self{
-            vals: eytz_vec,
-            num_iters: len.ilog2() as usize, 
+            num_iters: eytz_vec.len().ilog2() as usize, 
+            vals: eytz_vec.into(),
         }
     }
+}
+
+impl Ranked for Eytzinger{
+    fn len(&self) -> usize {
+        self.vals.len() - 1
+    }
 
-    fn get_funcs() -> Vec<&'static dyn SearchScheme<Self>> {
-        //&[&Eytzinger::eyz_search, &Eytzinger::search_prefetch, &Eytzinger::search_branchless, &Eytzinger::search_branchless_prefetch]
-        vec!(&Eytzinger::search_prefetch)
+    fn key_at(&self, mut rank: usize) -> u32 {
+        if rank >= self.len() {
+            return self.get(0);
+        }
+        let mut idx = 1;
+        loop {
+            let left = self.subtree_size(2 * idx);
+            if rank == left {
+                return self.get(idx);
+            }
+            idx = 2 * idx + (rank > left) as usize;
+            if rank > left {
+                rank -= left + 1;
+            }
+        }
+    }
+
+    fn lower_bound(&self, value: u32) -> usize {
+        let mut idx = 1;
+        while idx < self.vals.len() {
+            idx = 2 * idx + (value > self.get(idx)) as usize;
+        }
+        self.rank(search_result_to_index(idx))
+    }
+
+    fn range(&self, lo: u32, hi: u32) -> impl Iterator<Item = u32> + '_ {
+        let count = self.range_count(lo, hi);
+        let mut idx = 1;
+        while idx < self.vals.len() {
+            idx = 2 * idx + (lo > self.get(idx)) as usize;
+        }
+        let start = search_result_to_index(idx);
+        std::iter::successors(Some(start), |&idx| Some(self.successor(idx)))
+            .take(count)
+            .map(|idx| self.get(idx))
     }
+}
 
+impl Persist for Eytzinger{
+    const STRUCTURE: u32 = 1;
+    const NODE_WIDTH: u32 = 1;
+    type Node = u32;
 
+    fn parts(&self) -> (&[usize], &[u32]) {
+        (&[], &self.vals)
+    }
+
+    fn from_parts(len: usize, offsets: Vec<usize>, vals: Storage<u32>) -> Option<Self> {
+        (offsets.is_empty() && vals.len() == len + 1)
+            .then(|| Self { num_iters: vals.len().ilog2() as usize, vals })
+    }
 }
 
 impl Eytzinger{
+    /// Number of nodes in the subtree rooted at `idx`.
+    fn subtree_size(&self, idx: usize) -> usize {
+        let len = self.len();
+        if idx > len {
+            return 0;
+        }
+        let levels = len.ilog2() - idx.ilog2();
+        let last_level = (len + 1).saturating_sub(idx << levels).min(1 << levels);
+        (1 << levels) - 1 + last_level
+    }
+
+    /// In-order rank of the node at `idx`, where index 0 stands for "past the end".
+    fn rank(&self, idx: usize) -> usize {
+        if idx == 0 {
+            return self.len();
+        }
+        let mut rank = self.subtree_size(2 * idx);
+        for i in 0..idx.ilog2() {
+            if (idx >> i) & 1 == 1 {
+                rank += self.subtree_size(2 * (idx >> (i + 1))) + 1;
+            }
+        }
+        rank
+    }
+
+    /// Index of the next node in sorted order, or 0 after the last one.
+    fn successor(&self, mut idx: usize) -> usize {
+        if 2 * idx + 1 < self.vals.len() {
+            idx = 2 * idx + 1;
+            while 2 * idx < self.vals.len() {
+                idx *= 2;
+            }
+            idx
+        } else {
+            search_result_to_index(idx)
+        }
+    }
+
     fn get(&self, index: usize) -> u32 {
+        debug_assert!(index < self.vals.len(), "index {index} out of bounds");
+        // SAFETY: searches only read nodes below `self.vals.len()`, and the indices they end on
+        // are turned back into one of their ancestors or 0 before being read.
         unsafe { *self.vals.get_unchecked(index) }
     }
 
-    fn get_next_index_branchless(&self, idx: usize, q: u32) -> usize {
+    /// Turns the index a search ended on into the answer for query `q`.
+    ///
+    /// The lower bound is the last node the search turned left at, the predecessor the last
+    /// node it turned right at.
+    fn finish<K: QueryKind>(&self, q: u32, idx: usize) -> u32 {
+        match K::KIND {
+            Kind::LowerBound | Kind::UpperBound => self.get(search_result_to_index(idx)),
+            Kind::Predecessor => self.get(idx >> (idx.trailing_zeros() + 1)),
+            Kind::EqualRange | Kind::RangeCount => answer::<K, _>(self, q, self.rank(search_result_to_index(idx))),
+        }
+    }
+
+    fn get_next_index_branchless<K: QueryKind>(&self, idx: usize, q: u32) -> usize {
         let mut idx_u64 = 2 * idx as u64;
         let candidate = (2 * idx + 1) as u64;
         // the OR here is a hack; it is done to achieve the same result algorithmica does.
         // We have to do this because we're using unsigned integers and they are using signed, so they use -1 as their "value not found"
         // retval. Therefore, they can do their last check against -1 at position 0 in the vector, which always results in the comparison
         // being true.
+        // Predecessor queries strip trailing zeros instead, so there the extra step has to turn left.
 
         let in_bounds = idx < self.vals.len();
         let idx = if in_bounds { idx } else { 0 };
-        idx_u64.cmovnz(&candidate, (q > self.get(idx) || !in_bounds) as u8);
+        let forced = !in_bounds && K::KIND != Kind::Predecessor;
+        idx_u64.cmovnz(&candidate, (q > self.get(idx) || forced) as u8);
         idx_u64 as usize
     }
 
 
     #[inline(never)]
-    pub fn eyz_search(&self, q: u32) -> u32 {
+    pub fn eyz_search<K: QueryKind>(&self, q: u32) -> u32 {
+        let t = K::KIND.target(q);
         let mut idx = 1;
         while idx < self.vals.len() {
-            idx = 2 * idx + (q > self.get(idx)) as usize;
+            idx = 2 * idx + (t > self.get(idx)) as usize;
         }
-        idx = search_result_to_index(idx);
-        self.get(idx)
+        self.finish::<K>(q, idx)
     }
 
     #[inline(never)]
-    pub fn search_branchless(&self, q: u32) -> u32 {
+    pub fn search_branchless<K: QueryKind>(&self, q: u32) -> u32 {
+        let t = K::KIND.target(q);
         let mut idx = 1;
         // do a constant number of iterations
         for _ in 0..self.num_iters {
-            let jump_to = (q > self.get(idx)) as usize;
+            let jump_to = (t > self.get(idx)) as usize;
             idx = 2 * idx + jump_to;
         }
 
         // let cmp_idx = if idx < self.vals.len() { idx } else { 0 };
-        idx = self.get_next_index_branchless(idx, q);
-        idx = search_result_to_index(idx);
-        self.get(idx)
+        idx = self.get_next_index_branchless::<K>(idx, t);
+        self.finish::<K>(q, idx)
     }
 
     #[inline(never)]
-    pub fn search_prefetch(&self, q: u32) -> u32 {
+    pub fn search_prefetch<K: QueryKind>(&self, q: u32) -> u32 {
+        let t = K::KIND.target(q);
         let mut idx = 1;
         while (1 << 4) * idx < self.vals.len() {
-            idx = 2 * idx + (q > self.get(idx)) as usize;
+            idx = 2 * idx + (t > self.get(idx)) as usize;
             prefetch_index(&self.vals, (1 << 4) * idx);
         }
         while idx < self.vals.len() {
-            idx = 2 * idx + (q > self.get(idx)) as usize;
+            idx = 2 * idx + (t > self.get(idx)) as usize;
         }
-        idx = search_result_to_index(idx);
-        self.get(idx)
+        self.finish::<K>(q, idx)
     }
 
     #[inline(never)]
-    pub fn search_branchless_prefetch(&self, q: u32) -> u32 {
+    pub fn search_branchless_prefetch<K: QueryKind>(&self, q: u32) -> u32 {
+        let t = K::KIND.target(q);
         let mut idx = 1;
-        let prefetch_until = self.num_iters as isize - 4_isize;
+        let prefetch_until = self.num_iters.saturating_sub(4);
         for _ in 0..prefetch_until {
-            let jump_to = (q > self.get(idx)) as usize;
+            let jump_to = (t > self.get(idx)) as usize;
             idx = 2 * idx + jump_to;
             // the extra prefetch is apparently very slow here; why?
             prefetch_index(&self.vals, (1 << 4) * idx);
         }
 
-        for _ in prefetch_until..(self.num_iters as isize) {
-            let jump_to = (q > self.get(idx)) as usize;
+        for _ in prefetch_until..self.num_iters {
+            let jump_to = (t > self.get(idx)) as usize;
             idx = 2 * idx + jump_to;
         }
 
-        idx = self.get_next_index_branchless(idx, q);
-        idx = search_result_to_index(idx);
-        self.get(idx)
+        idx = self.get_next_index_branchless::<K>(idx, t);
+        self.finish::<K>(q, idx)
     }
 
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::query::query_kind::LowerBound;
+
+    use super::*;
+
+    #[test]
+    fn test_branchless_prefetch_small_trees() {
+        // Trees of fewer than four levels have nothing to prefetch.
+        for len in 0..40u32 {
+            let vals: Vec<u32> = (0..len).map(|i| 2 * i + 1).collect();
+            let eytzinger = Eytzinger::new(&vals);
+            for q in 0..2 * len + 2 {
+                assert_eq!(
+                    eytzinger.search_branchless_prefetch::<LowerBound>(q),
+                    eytzinger.search_branchless::<LowerBound>(q),
+                    "len {len}, query {q}",
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    fmt,
+    io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom},
+};
+
+/// Why a structure could not be built from a stream of keys.
+#[derive(Debug)]
+pub enum BuildError {
+    /// The key at `index` is smaller than the one before it.
+    Unsorted { index: usize },
+    /// The key at `index` is `key`, above `max`, the largest key the structure takes.
+    KeyTooLarge { index: usize, key: u32, max: u32 },
+    /// The input ended after `found` of the `expected` keys.
+    TooFewKeys { expected: usize, found: usize },
+    Io(io::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Unsorted { index } => write!(f, "key {index} is smaller than the key before it"),
+            BuildError::KeyTooLarge { index, key, max } => write!(f, "key {index} is {key}, above the largest allowed key {max}"),
+            BuildError::TooFewKeys { expected, found } => write!(f, "expected {expected} keys, found {found}"),
+            BuildError::Io(err) => write!(f, "failed to read keys: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BuildError {
+    fn from(err: io::Error) -> Self {
+        BuildError::Io(err)
+    }
+}
+
+/// A structure that can be built in one pass over its keys, without a copy of them.
+///
+/// Peak memory is the final structure plus whatever the input holds.
+pub trait FromSorted: Sized {
+    /// The largest key the structure takes.
+    const MAX_KEY: u32;
+
+    /// Builds the structure from `len` keys in sorted order, none of them above `MAX_KEY`.
+    ///
+    /// `keys` may end early, in which case the result is discarded by the caller.
+    fn from_exact(len: usize, keys: impl Iterator<Item = u32>) -> Self;
+
+    /// Builds the structure from keys in sorted order, rejecting them if they are not or if
+    /// one is above `MAX_KEY`.
+    fn from_sorted_iter<I>(keys: I) -> Result<Self, BuildError>
+    where
+        I: IntoIterator<Item = u32>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let keys = keys.into_iter();
+        build(keys.len(), keys.map(Ok))
+    }
+
+    /// Builds the structure from a file of sorted little-endian `u32` keys.
+    fn from_sorted_reader(mut reader: impl Read + Seek) -> Result<Self, BuildError> {
+        let start = reader.stream_position()?;
+        let bytes = reader.seek(SeekFrom::End(0))? - start;
+        reader.seek(SeekFrom::Start(start))?;
+        if bytes % size_of::<u32>() as u64 != 0 {
+            return Err(io::Error::new(ErrorKind::InvalidData, "file size is not a multiple of the key size").into());
+        }
+        let len = usize::try_from(bytes / size_of::<u32>() as u64)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "too many keys"))?;
+
+        let mut reader = BufReader::new(reader);
+        let keys = std::iter::repeat_with(move || {
+            let mut key = [0; size_of::<u32>()];
+            reader.read_exact(&mut key).map(|()| u32::from_le_bytes(key))
+        });
+        build(len, keys)
+    }
+}
+
+fn build<S: FromSorted>(len: usize, keys: impl Iterator<Item = io::Result<u32>>) -> Result<S, BuildError> {
+    let mut checked = Checked { keys, max: S::MAX_KEY, prev: 0, found: 0, error: None };
+    let s = S::from_exact(len, checked.by_ref().take(len));
+    match checked.error {
+        Some(err) => Err(err),
+        None if checked.found < len => Err(BuildError::TooFewKeys { expected: len, found: checked.found }),
+        None => Ok(s),
+    }
+}
+
+/// Passes keys through until one is out of order, too large or fails to read, and remembers
+/// why it stopped.
+struct Checked<I> {
+    keys: I,
+    max: u32,
+    prev: u32,
+    found: usize,
+    error: Option<BuildError>,
+}
+
+impl<I: Iterator<Item = io::Result<u32>>> Iterator for Checked<I> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.keys.next()? {
+            Ok(key) if key < self.prev => {
+                self.error = Some(BuildError::Unsorted { index: self.found });
+                None
+            }
+            Ok(key) if key > self.max => {
+                self.error = Some(BuildError::KeyTooLarge { index: self.found, key, max: self.max });
+                None
+            }
+            Ok(key) => {
+                self.prev = key;
+                self.found += 1;
+                Some(key)
+            }
+            Err(err) => {
+                self.error = Some(err.into());
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{query::searchable::Searchable, searches::{b_eytzinger::BEytzinger, binary_search::SortedVec, eytzinger::Eytzinger, ranked::Ranked, s_tree::{STree, MAX}}};
+
+    use super::*;
+
+    fn check<S: Searchable + FromSorted + Ranked>() {
+        for len in [0, 1, 16, 17, 272, 273, 4913] {
+            let vals: Vec<u32> = (0..len).map(|i| i / 2 * 3 + 1).collect();
+            let bytes: Vec<u8> = vals.iter().flat_map(|v| v.to_le_bytes()).collect();
+            let expected = S::new(&vals);
+            let from_iter = S::from_sorted_iter(vals.iter().copied()).unwrap();
+            let from_reader = S::from_sorted_reader(Cursor::new(bytes)).unwrap();
+            for q in 0..3 * len / 2 + 3 {
+                assert_eq!(from_iter.lower_bound(q), expected.lower_bound(q), "len {len}, query {q}");
+                assert_eq!(from_reader.lower_bound(q), expected.lower_bound(q), "len {len}, query {q}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_matches_new() {
+        check::<SortedVec>();
+        check::<Eytzinger>();
+        check::<STree>();
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let err = STree::from_sorted_iter([1, 2, 3, 2, 4]).unwrap_err();
+        assert!(matches!(err, BuildError::Unsorted { index: 3 }), "{err}");
+
+        let bytes: Vec<u8> = [5u32, 4].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let err = Eytzinger::from_sorted_reader(Cursor::new(bytes)).unwrap_err();
+        assert!(matches!(err, BuildError::Unsorted { index: 1 }), "{err}");
+
+        let err = SortedVec::from_sorted_reader(Cursor::new(vec![0; 7])).unwrap_err();
+        assert!(matches!(err, BuildError::Io(_)), "{err}");
+
+        let err = BEytzinger::from_sorted_iter([3, 1]).unwrap_err();
+        assert!(matches!(err, BuildError::Unsorted { index: 1 }), "{err}");
+
+        // The signed compares of the S-trees would misorder these.
+        let err = STree::from_sorted_iter([1, 3_000_000_000, 3_000_000_001]).unwrap_err();
+        assert!(matches!(err, BuildError::KeyTooLarge { index: 1, key: 3_000_000_000, max: MAX }), "{err}");
+        let err = BEytzinger::from_sorted_iter([MAX, MAX + 1]).unwrap_err();
+        assert!(matches!(err, BuildError::KeyTooLarge { index: 1, .. }), "{err}");
+        assert!(STree::from_sorted_iter([1, MAX]).is_ok());
+
+        let bytes: Vec<u8> = [1, u32::MAX].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let err = Eytzinger::from_sorted_reader(Cursor::new(bytes)).unwrap_err();
+        assert!(matches!(err, BuildError::KeyTooLarge { index: 1, .. }), "{err}");
+        let err = SortedVec::from_sorted_iter([u32::MAX]).unwrap_err();
+        assert!(matches!(err, BuildError::KeyTooLarge { index: 0, .. }), "{err}");
+        assert!(SortedVec::from_sorted_iter([1, u32::MAX - 1]).is_ok());
+    }
+}
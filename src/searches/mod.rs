@@ -1,4 +1,14 @@
+pub mod b_eytzinger;
 pub mod binary_search;
+pub mod dynamic;
 pub mod eytzinger;
+pub mod from_sorted;
+pub mod payload;
+pub mod persist;
+pub mod prefix_lut;
+pub mod ranked;
 pub mod s_tree;
-pub mod s_tree_node;
+pub mod s_tree_compressed;
+pub mod storage;
+pub(crate) mod s_tree_node;
+pub mod veb_tree;
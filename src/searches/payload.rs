@@ -0,0 +1,329 @@
+use std::array::from_fn;
+
+use crate::{query::{searchable::{batched, SearchScheme, Searchable}, query_kind::{Kind, QueryKind}}, utils::prefetch_index};
+
+use super::{ranked::Ranked, s_tree::{STree, MAX, NODE_LEN}, s_tree_node::STreeNode, storage::Pod};
+
+/// A value stored with each key, such as a row id or an offset.
+pub trait Payload: Pod {
+    /// The payload `Searchable::new` stores for the key with this rank.
+    fn from_rank(rank: usize) -> Self;
+
+    /// What a search scheme reports for this payload, since schemes return `u32`.
+    fn low_bits(self) -> u32;
+}
+
+impl Payload for u32 {
+    fn from_rank(rank: usize) -> Self {
+        rank as u32
+    }
+
+    fn low_bits(self) -> u32 {
+        self
+    }
+}
+
+impl Payload for u64 {
+    fn from_rank(rank: usize) -> Self {
+        rank as u64
+    }
+
+    fn low_bits(self) -> u32 {
+        self as u32
+    }
+}
+
+/// Looks up the payloads stored with the keys.
+pub trait PayloadLookup<V: Payload> {
+    /// The first key `>= key` and its payload, if there is one.
+    fn lower_bound_entry(&self, key: u32) -> Option<(u32, V)>;
+
+    /// The payload of `key`, if it is stored. With duplicate keys this is the payload of the
+    /// first copy.
+    fn get(&self, key: u32) -> Option<V> {
+        self.lower_bound_entry(key).filter(|&(found, _)| found == key).map(|(_, value)| value)
+    }
+}
+
+/// Payloads for the keys of `S`, in a separate array in rank order.
+#[derive(Debug)]
+pub struct WithPayloads<S, V> {
+    inner: S,
+    payloads: Vec<V>,
+}
+
+impl<S: Ranked, V: Payload> WithPayloads<S, V> {
+    /// Stores `payloads[rank]` with the key of rank `rank` of `inner`.
+    pub fn new(inner: S, payloads: Vec<V>) -> Self {
+        assert_eq!(inner.len(), payloads.len(), "Need exactly one payload per key");
+        Self { inner, payloads }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// The payload reported for the answer of query `value`, given the rank of its target.
+    #[inline(always)]
+    fn finish<K: QueryKind>(&self, rank: usize) -> u32 {
+        let rank = if K::KIND == Kind::Predecessor { rank.wrapping_sub(1) } else { rank };
+        self.payloads.get(rank).map_or(u32::MAX, |v| v.low_bits())
+    }
+
+    #[inline(never)]
+    fn search<K: QueryKind>(&self, value: u32) -> u32 {
+        self.finish::<K>(self.inner.lower_bound(K::KIND.target(value)))
+    }
+
+    #[inline(never)]
+    fn batch<const P: usize, K: QueryKind>(&self, values: &[u32; P]) -> [u32; P] {
+        let ranks = self.inner.batch_lower_bound(&values.map(|v| K::KIND.target(v)));
+        ranks.map(|rank| self.finish::<K>(rank))
+    }
+}
+
+impl<S: Ranked, V: Payload> PayloadLookup<V> for WithPayloads<S, V> {
+    fn lower_bound_entry(&self, key: u32) -> Option<(u32, V)> {
+        let rank = self.inner.lower_bound(key);
+        self.payloads.get(rank).map(|&v| (self.inner.key_at(rank), v))
+    }
+}
+
+impl<S: Searchable + Ranked + 'static, V: Payload> Searchable for WithPayloads<S, V> {
+    fn new(sorted_vals: &[u32]) -> Self {
+        Self::new(S::new(sorted_vals), (0..sorted_vals.len()).map(V::from_rank).collect())
+    }
+
+    /// Schemes report the payload of the answer instead of the key, or `u32::MAX` if there is none.
+    fn get_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        if K::KIND.needs_rank() {
+            return vec!();
+        }
+        let batch_128 = Box::leak(Box::new(batched(Self::batch::<128, K>)));
+        vec!(&Self::search::<K>, batch_128)
+    }
+}
+
+/// A leaf of `STreeMap`: the keys, followed by their payloads.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, align(64))]
+struct Leaf<V> {
+    keys: STreeNode,
+    payloads: [V; NODE_LEN],
+}
+
+/// An S-tree whose leaves hold the payloads next to the keys, so a lookup reads the payload
+/// from the leaf it already has in cache.
+#[derive(Debug)]
+pub struct STreeMap<V> {
+    internal: Vec<STreeNode>,
+    offsets: Vec<usize>,
+    leaves: Vec<Leaf<V>>,
+    len: usize,
+}
+
+impl<V: Payload> STreeMap<V> {
+    /// Stores `payloads[i]` with `sorted_keys[i]`.
+    pub fn from_sorted(sorted_keys: &[u32], payloads: &[V]) -> Self {
+        assert_eq!(sorted_keys.len(), payloads.len(), "Need exactly one payload per key");
+        let tree = STree::new(sorted_keys);
+        let (internal, offsets, leaves) = tree.layers();
+        let leaves = leaves.iter().enumerate().map(|(i, &keys)| Leaf {
+            keys,
+            // Slots without a key are never read.
+            payloads: from_fn(|j| payloads.get(i * NODE_LEN + j).copied().unwrap_or(V::from_rank(0))),
+        }).collect();
+        Self { internal: internal.to_vec(), offsets: offsets.to_vec(), leaves, len: sorted_keys.len() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn node(&self, node_idx: usize) -> &STreeNode {
+        debug_assert!(node_idx < self.internal.len(), "node {node_idx} out of bounds");
+        // SAFETY: as in `STree`, searches only descend into children that exist.
+        unsafe { self.internal.get_unchecked(node_idx) }
+    }
+
+    fn leaf(&self, leaf_idx: usize) -> &Leaf<V> {
+        debug_assert!(leaf_idx < self.leaves.len(), "leaf {leaf_idx} out of bounds");
+        // SAFETY: a full last leaf is followed by the sentinel leaf.
+        unsafe { self.leaves.get_unchecked(leaf_idx) }
+    }
+
+    /// Index of the leaf holding the lower bound of `value`.
+    #[inline(always)]
+    fn find_leaf(&self, value: u32) -> usize {
+        let mut node_idx = 0;
+        for &offset in &self.offsets {
+            let jump_to = self.node(offset + node_idx).find_popcnt(value);
+            node_idx = node_idx * (NODE_LEN + 1) + jump_to;
+        }
+        node_idx
+    }
+
+    /// The payload reported for the answer of query `value`, given the rank of its target.
+    #[inline(always)]
+    fn finish<K: QueryKind>(&self, rank: usize) -> u32 {
+        let rank = if K::KIND == Kind::Predecessor { rank.wrapping_sub(1) } else { rank };
+        if rank < self.len {
+            self.leaf(rank / NODE_LEN).payloads[rank % NODE_LEN].low_bits()
+        } else {
+            u32::MAX
+        }
+    }
+
+    /// Rank of the first key `>= value`, or `len` if there is none.
+    #[inline(always)]
+    fn lower_bound(&self, value: u32) -> usize {
+        // Keys are compared as i32, so anything past MAX has to be clamped.
+        let value = value.min(MAX);
+        let leaf_idx = self.find_leaf(value);
+        leaf_idx * NODE_LEN + self.leaf(leaf_idx).keys.find_popcnt(value)
+    }
+
+    #[inline(never)]
+    fn search<K: QueryKind>(&self, value: u32) -> u32 {
+        self.finish::<K>(self.lower_bound(K::KIND.target(value)))
+    }
+
+    #[inline(never)]
+    fn batch_prefetch<const P: usize, K: QueryKind>(&self, values: &[u32; P]) -> [u32; P] {
+        let targets = values.map(|v| K::KIND.target(v).min(MAX));
+        let mut k = [0; P];
+        for (h, &o) in self.offsets.iter().enumerate() {
+            for i in 0..P {
+                let jump_to = self.node(o + k[i]).find_popcnt(targets[i]);
+                k[i] = k[i] * (NODE_LEN + 1) + jump_to;
+                match self.offsets.get(h + 1) {
+                    Some(&next) => prefetch_index(&self.internal, next + k[i]),
+                    None => prefetch_index(&self.leaves, k[i]),
+                }
+            }
+        }
+        from_fn(|i| self.finish::<K>(k[i] * NODE_LEN + self.leaf(k[i]).keys.find_popcnt(targets[i])))
+    }
+}
+
+impl<V: Payload> PayloadLookup<V> for STreeMap<V> {
+    fn lower_bound_entry(&self, key: u32) -> Option<(u32, V)> {
+        let rank = self.lower_bound(key);
+        (rank < self.len).then(|| {
+            let leaf = self.leaf(rank / NODE_LEN);
+            (leaf.keys.keys[rank % NODE_LEN], leaf.payloads[rank % NODE_LEN])
+        })
+    }
+}
+
+impl<V: Payload> Searchable for STreeMap<V> {
+    fn new(sorted_vals: &[u32]) -> Self {
+        let payloads: Vec<V> = (0..sorted_vals.len()).map(V::from_rank).collect();
+        Self::from_sorted(sorted_vals, &payloads)
+    }
+
+    /// Schemes report the payload of the answer instead of the key, or `u32::MAX` if there is none.
+    fn get_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        if K::KIND.needs_rank() {
+            return vec!();
+        }
+        let batch_128_prefetch = Box::leak(Box::new(batched(Self::batch_prefetch::<128, K>)));
+        vec!(&Self::search::<K>, batch_128_prefetch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{query::query_kind::{LowerBound, Predecessor, UpperBound}, searches::{binary_search::SortedVec, eytzinger::Eytzinger}};
+
+    use super::*;
+
+    /// Checks lookups and schemes, with the payload of each key being its rank.
+    fn check<S: Searchable + PayloadLookup<u32> + 'static>() {
+        for len in (0..100).chain([4912, 4913, 4914]) {
+            let vals: Vec<u32> = (0..len).map(|i| 3 * i + 1).collect();
+            let s = S::new(&vals);
+            let mut queries: Vec<u32> = (0..3 * len + 3).collect();
+            queries.resize(queries.len().next_multiple_of(128), 0);
+
+            for &q in &queries {
+                let rank = vals.partition_point(|&x| x < q);
+                let exact = (vals.get(rank) == Some(&q)).then_some(rank as u32);
+                assert_eq!(s.get(q), exact, "len {len}, key {q}");
+                assert_eq!(s.lower_bound_entry(q), vals.get(rank).map(|&k| (k, rank as u32)), "len {len}, key {q}");
+            }
+
+            let payload = |rank: Option<usize>| rank.filter(|&r| r < vals.len()).map_or(u32::MAX, |r| r as u32);
+            let lower: Vec<u32> = queries.iter().map(|&q| payload(Some(vals.partition_point(|&x| x < q)))).collect();
+            let upper: Vec<u32> = queries.iter().map(|&q| payload(Some(vals.partition_point(|&x| x <= q)))).collect();
+            let pred: Vec<u32> = queries.iter().map(|&q| payload(vals.partition_point(|&x| x <= q).checked_sub(1))).collect();
+            for func in S::get_funcs::<LowerBound>() {
+                assert_eq!(func.query(&s, &queries), lower, "len {len}, {}", func.get_name());
+            }
+            for func in S::get_funcs::<UpperBound>() {
+                assert_eq!(func.query(&s, &queries), upper, "len {len}, {}", func.get_name());
+            }
+            for func in S::get_funcs::<Predecessor>() {
+                assert_eq!(func.query(&s, &queries), pred, "len {len}, {}", func.get_name());
+            }
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_parallel_array() {
+        check::<WithPayloads<SortedVec, u32>>();
+        check::<WithPayloads<Eytzinger, u32>>();
+        check::<WithPayloads<STree, u32>>();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_interleaved() {
+        check::<STreeMap<u32>>();
+    }
+
+    #[test]
+    fn test_largest_key() {
+        let s = <WithPayloads<SortedVec, u32> as Searchable>::new(&[1, u32::MAX - 1]);
+        let queries = [u32::MAX - 1, u32::MAX];
+        for func in WithPayloads::<SortedVec, u32>::get_funcs::<Predecessor>() {
+            assert_eq!(func.query(&s, &queries), [1, 1], "{}", func.get_name());
+        }
+        for func in WithPayloads::<SortedVec, u32>::get_funcs::<UpperBound>() {
+            assert_eq!(func.query(&s, &queries), [u32::MAX, u32::MAX], "{}", func.get_name());
+        }
+    }
+
+    #[test]
+    fn test_u64_payloads() {
+        let keys = [2, 3, 3, 10];
+        let payloads = [1 << 40, 7, 8, u64::MAX];
+        let map = STreeMap::from_sorted(&keys, &payloads);
+        let parallel = WithPayloads::new(SortedVec::new(&keys), payloads.to_vec());
+        for (key, expected) in [(2, Some(1 << 40)), (3, Some(7)), (4, None), (10, Some(u64::MAX))] {
+            assert_eq!(map.get(key), expected, "key {key}");
+            assert_eq!(parallel.get(key), expected, "key {key}");
+        }
+        assert_eq!(map.lower_bound_entry(4), Some((10, u64::MAX)));
+        assert_eq!(parallel.lower_bound_entry(11), None);
+    }
+
+    #[test]
+    fn test_duplicates_return_first_copy() {
+        // Runs of 40 equal keys cross every leaf boundary.
+        let keys: Vec<u32> = (0..1000).map(|i| i / 40 * 2).collect();
+        let map = STreeMap::<u32>::new(&keys);
+        let parallel = <WithPayloads<Eytzinger, u32> as Searchable>::new(&keys);
+        for q in 0..52 {
+            let first = keys.partition_point(|&x| x < q);
+            let expected = (keys.get(first) == Some(&q)).then_some(first as u32);
+            assert_eq!(map.get(q), expected, "key {q}");
+            assert_eq!(parallel.get(q), expected, "key {q}");
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use memmap2::Mmap;
+
+use super::{ranked::Ranked, storage::{as_bytes, as_bytes_mut, Mapped, Pod, Storage}};
+
+const MAGIC: [u8; 8] = *b"BSEARCH\0";
+pub const FORMAT_VERSION: u32 = 1;
+const BYTE_ORDER: u32 = 0x0102_0304;
+const HEADER_LEN: usize = 64;
+// Node arrays start on a multiple of this, so mapped nodes keep their `align(64)`.
+const DATA_ALIGN: usize = 64;
+
+/// Fixed size header at the start of every file.
+///
+/// Integers are stored in native byte order; `byte_order` makes a file from a machine with
+/// the other order fail to load instead of being misread.
+#[derive(Debug, PartialEq, Eq)]
+struct Header {
+    version: u32,
+    byte_order: u32,
+    structure: u32,
+    key_bits: u32,
+    node_width: u32,
+    node_size: u32,
+    len: u64,
+    n_offsets: u64,
+    n_nodes: u64,
+    checksum: u32,
+}
+
+impl Header {
+    fn for_parts<S: Persist>(len: usize, n_offsets: usize, n_nodes: usize, checksum: u32) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            byte_order: BYTE_ORDER,
+            structure: S::STRUCTURE,
+            key_bits: u32::BITS,
+            node_width: S::NODE_WIDTH,
+            node_size: size_of::<S::Node>() as u32,
+            len: len as u64,
+            n_offsets: n_offsets as u64,
+            n_nodes: n_nodes as u64,
+            checksum,
+        }
+    }
+
+    fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[..8].copy_from_slice(&MAGIC);
+        let fields = [self.version, self.byte_order, self.structure, self.key_bits, self.node_width, self.node_size];
+        for (i, field) in fields.iter().enumerate() {
+            bytes[8 + 4 * i..12 + 4 * i].copy_from_slice(&field.to_ne_bytes());
+        }
+        bytes[32..40].copy_from_slice(&self.len.to_ne_bytes());
+        bytes[40..48].copy_from_slice(&self.n_offsets.to_ne_bytes());
+        bytes[48..56].copy_from_slice(&self.n_nodes.to_ne_bytes());
+        bytes[56..60].copy_from_slice(&self.checksum.to_ne_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; HEADER_LEN]) -> io::Result<Self> {
+        if bytes[..8] != MAGIC {
+            return Err(invalid("not a search structure file"));
+        }
+        let u32_at = |i: usize| u32::from_ne_bytes(bytes[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_ne_bytes(bytes[i..i + 8].try_into().unwrap());
+        Ok(Self {
+            version: u32_at(8),
+            byte_order: u32_at(12),
+            structure: u32_at(16),
+            key_bits: u32_at(20),
+            node_width: u32_at(24),
+            node_size: u32_at(28),
+            len: u64_at(32),
+            n_offsets: u64_at(40),
+            n_nodes: u64_at(48),
+            checksum: u32_at(56),
+        })
+    }
+
+    /// Rejects files whose layout differs from what `S` expects on this machine.
+    fn check<S: Persist>(&self) -> io::Result<()> {
+        let expected = Self::for_parts::<S>(0, 0, 0, 0);
+        let checks = [
+            ("format version", self.version, expected.version),
+            ("byte order", self.byte_order, expected.byte_order),
+            ("structure", self.structure, expected.structure),
+            ("key bits", self.key_bits, expected.key_bits),
+            ("node width", self.node_width, expected.node_width),
+            ("node size", self.node_size, expected.node_size),
+        ];
+        for (name, found, expected) in checks {
+            if found != expected {
+                return Err(invalid(format!("{name} is {found:#x}, expected {expected:#x}")));
+            }
+        }
+        Ok(())
+    }
+
+    fn offsets_len(&self) -> io::Result<usize> {
+        usize::try_from(self.n_offsets).map_err(|_| invalid("too many offsets"))
+    }
+
+    fn nodes_len(&self) -> io::Result<usize> {
+        usize::try_from(self.n_nodes).map_err(|_| invalid("too many nodes"))
+    }
+
+    /// Byte position of the node array.
+    fn data_start(&self) -> io::Result<usize> {
+        self.offsets_len()?
+            .checked_mul(size_of::<u64>())
+            .and_then(|bytes| bytes.checked_add(HEADER_LEN))
+            .map(|end| end.next_multiple_of(DATA_ALIGN))
+            .ok_or_else(|| invalid("too many offsets"))
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.into())
+}
+
+fn checksum(len: u64, offsets: &[u64], nodes: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&len.to_ne_bytes());
+    hasher.update(as_bytes(offsets));
+    hasher.update(nodes);
+    hasher.finalize()
+}
+
+/// A search structure with a versioned on-disk format.
+///
+/// A file holds a header with the layout parameters and a checksum over the key count,
+/// the per-layer offsets and the node array, followed by the offsets and the nodes. Loading
+/// fails if any layout parameter differs from what this build expects, if the checksum does
+/// not match, or if the nodes break an invariant the searches rely on.
+pub trait Persist: Ranked + Sized {
+    /// Identifies the structure in the file header.
+    const STRUCTURE: u32;
+    /// Keys per node.
+    const NODE_WIDTH: u32;
+    type Node: Pod;
+
+    /// The per-layer offsets and the node array.
+    fn parts(&self) -> (&[usize], &[Self::Node]);
+
+    /// Reassembles the structure, or returns `None` if the parts don't form a valid structure
+    /// of `len` keys.
+    ///
+    /// Files only carry a checksum against accidental damage, so this has to check every
+    /// invariant that unchecked indexing in the searches depends on.
+    fn from_parts(len: usize, offsets: Vec<usize>, nodes: Storage<Self::Node>) -> Option<Self>;
+
+    fn write_to(&self, mut w: impl Write) -> io::Result<()> {
+        let (offsets, nodes) = self.parts();
+        let offsets: Vec<u64> = offsets.iter().map(|&o| o as u64).collect();
+        let header = Header::for_parts::<Self>(self.len(), offsets.len(), nodes.len(), checksum(self.len() as u64, &offsets, as_bytes(nodes)));
+
+        w.write_all(&header.to_bytes())?;
+        w.write_all(as_bytes(&offsets))?;
+        let padding = header.data_start()? - HEADER_LEN - size_of_val(&offsets[..]);
+        w.write_all(&[0; DATA_ALIGN][..padding])?;
+        w.write_all(as_bytes(nodes))?;
+        w.flush()
+    }
+
+    fn read_from(mut r: impl Read) -> io::Result<Self> {
+        let mut bytes = [0; HEADER_LEN];
+        r.read_exact(&mut bytes)?;
+        let header = Header::from_bytes(&bytes)?;
+        header.check::<Self>()?;
+
+        let offsets: Vec<u64> = read_items(&mut r, header.offsets_len()?)?;
+        let padding = header.data_start()? - HEADER_LEN - size_of_val(&offsets[..]);
+        r.read_exact(&mut [0; DATA_ALIGN][..padding])?;
+        let nodes: Vec<Self::Node> = read_items(&mut r, header.nodes_len()?)?;
+
+        if checksum(header.len, &offsets, as_bytes(&nodes)) != header.checksum {
+            return Err(invalid("checksum mismatch"));
+        }
+        assemble::<Self>(&header, offsets, Storage::Owned(nodes))
+    }
+
+    fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+
+    /// Loads the structure without copying the node array, which stays mapped from the file.
+    ///
+    /// The whole file is still read once to verify the checksum.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while the structure is alive.
+    unsafe fn load_mmap(path: impl AsRef<Path>) -> io::Result<Self> {
+        let map = unsafe { Mmap::map(&File::open(path)?)? };
+        let bytes: &[u8; HEADER_LEN] = map.get(..HEADER_LEN)
+            .ok_or_else(|| invalid("file too short"))?
+            .try_into()
+            .unwrap();
+        let header = Header::from_bytes(bytes)?;
+        header.check::<Self>()?;
+
+        let start = header.data_start()?;
+        let offsets_end = HEADER_LEN + header.offsets_len()? * size_of::<u64>();
+        let offsets: Vec<u64> = map.get(HEADER_LEN..offsets_end)
+            .ok_or_else(|| invalid("file too short"))?
+            .chunks_exact(size_of::<u64>())
+            .map(|b| u64::from_ne_bytes(b.try_into().unwrap()))
+            .collect();
+
+        let nodes = Mapped::new(map, start, header.nodes_len()?)
+            .ok_or_else(|| invalid("file too short"))?;
+        let nodes = Storage::Mapped(nodes);
+        if checksum(header.len, &offsets, as_bytes(&nodes)) != header.checksum {
+            return Err(invalid("checksum mismatch"));
+        }
+        assemble::<Self>(&header, offsets, nodes)
+    }
+}
+
+/// Reads `n` items, growing the buffer as they arrive. A corrupt count then fails on the
+/// short read instead of on allocating for all of them up front.
+fn read_items<T: Pod>(r: &mut impl Read, n: usize) -> io::Result<Vec<T>> {
+    const CHUNK_BYTES: usize = 1 << 20;
+    let chunk = (CHUNK_BYTES / size_of::<T>()).max(1);
+    let mut items = Vec::new();
+    while items.len() < n {
+        let start = items.len();
+        // SAFETY: all zeroes is a valid `Pod` value.
+        items.resize(start + chunk.min(n - start), unsafe { std::mem::zeroed::<T>() });
+        r.read_exact(as_bytes_mut(&mut items[start..]))?;
+    }
+    Ok(items)
+}
+
+fn assemble<S: Persist>(header: &Header, offsets: Vec<u64>, nodes: Storage<S::Node>) -> io::Result<S> {
+    let len = usize::try_from(header.len).map_err(|_| invalid("too many keys"))?;
+    let offsets = offsets.into_iter()
+        .map(usize::try_from)
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid("offset out of range"))?;
+    S::from_parts(len, offsets, nodes).ok_or_else(|| invalid("nodes do not form a valid structure of that many keys"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{query::searchable::Searchable, searches::{binary_search::SortedVec, eytzinger::Eytzinger, s_tree::STree}};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("binary_search_{}_{name}", std::process::id()))
+    }
+
+    fn same_answers(a: &impl Ranked, b: &impl Ranked, vals: &[u32]) {
+        assert_eq!(a.len(), b.len());
+        for q in (0..vals.len() as u32 * 3 + 3).step_by(7) {
+            assert_eq!(a.lower_bound(q), b.lower_bound(q), "query {q}");
+            assert_eq!(a.key_at(a.lower_bound(q)), b.key_at(b.lower_bound(q)), "query {q}");
+        }
+    }
+
+    fn check<S: Searchable + Persist>(name: &str) {
+        let path = temp_path(name);
+        for len in [0, 1, 16, 17, 272, 273, 4913] {
+            let vals: Vec<u32> = (0..len).map(|i| 3 * i + 1).collect();
+            let s = S::new(&vals);
+            s.save(&path).unwrap();
+
+            same_answers(&s, &S::load(&path).unwrap(), &vals);
+            let mapped = unsafe { S::load_mmap(&path) }.unwrap();
+            same_answers(&s, &mapped, &vals);
+            assert!(mapped.parts().1.as_ptr().is_aligned());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_round_trip() {
+        check::<SortedVec>("sorted_vec");
+        check::<Eytzinger>("eytzinger");
+        check::<STree>("s_tree");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_rejects_bad_files() {
+        let path = temp_path("bad");
+        let vals: Vec<u32> = (0..1000).collect();
+        STree::new(&vals).save(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        assert!(SortedVec::load(&path).is_err(), "wrong structure");
+        assert!(Eytzinger::load(&path).is_err(), "wrong structure");
+
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, &corrupt).unwrap();
+        assert!(STree::load(&path).is_err(), "checksum");
+        assert!(unsafe { STree::load_mmap(&path) }.is_err(), "checksum");
+
+        let mut wrong_width = bytes.clone();
+        wrong_width[24] ^= 1;
+        std::fs::write(&path, &wrong_width).unwrap();
+        assert!(STree::load(&path).is_err(), "node width");
+
+        let mut wrong_len = bytes.clone();
+        wrong_len[32] ^= 1;
+        std::fs::write(&path, &wrong_len).unwrap();
+        assert!(STree::load(&path).is_err(), "key count");
+
+        // Counts far beyond the file have to fail on the short read, not on allocating for them.
+        for (name, at) in [("offset count", 40), ("node count", 48)] {
+            for count in [1u64 << 60, u64::MAX] {
+                let mut huge = bytes.clone();
+                huge[at..at + 8].copy_from_slice(&count.to_ne_bytes());
+                std::fs::write(&path, &huge).unwrap();
+                assert!(STree::load(&path).is_err(), "{name} {count}");
+                assert!(unsafe { STree::load_mmap(&path) }.is_err(), "{name} {count}");
+            }
+        }
+
+        // Damage with a matching checksum still has to be caught before a search walks it.
+        let header = Header::from_bytes(bytes[..HEADER_LEN].try_into().unwrap()).unwrap();
+        let start = header.data_start().unwrap();
+        let offsets: Vec<u64> = bytes[HEADER_LEN..HEADER_LEN + 8 * header.n_offsets as usize]
+            .chunks_exact(8)
+            .map(|b| u64::from_ne_bytes(b.try_into().unwrap()))
+            .collect();
+        let resealed = |edit: &dyn Fn(&mut [u8])| {
+            let mut bytes = bytes.clone();
+            edit(&mut bytes[start..]);
+            let checksum = checksum(header.len, &offsets, &bytes[start..]);
+            bytes[56..60].copy_from_slice(&checksum.to_ne_bytes());
+            bytes
+        };
+        let root_key = |key: u32| move |nodes: &mut [u8]| nodes[4..8].copy_from_slice(&key.to_ne_bytes());
+        for (name, corrupt) in [
+            ("internal key", resealed(&root_key(5))),
+            ("key above MAX", resealed(&root_key(u32::MAX))),
+            ("unsorted leaves", resealed(&|nodes| nodes[nodes.len() - 124] = 0)),
+            ("sentinel", resealed(&|nodes| nodes[nodes.len() - 4] = 0)),
+        ] {
+            std::fs::write(&path, &corrupt).unwrap();
+            assert!(STree::load(&path).is_err(), "{name}");
+            assert!(unsafe { STree::load_mmap(&path) }.is_err(), "{name}");
+        }
+        std::fs::write(&path, resealed(&|_| {})).unwrap();
+        assert!(STree::load(&path).is_ok(), "resealed but unchanged");
+
+        std::fs::write(&path, &bytes[..bytes.len() - 64]).unwrap();
+        assert!(STree::load(&path).is_err(), "truncated");
+        assert!(unsafe { STree::load_mmap(&path) }.is_err(), "truncated");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+use crate::query::{searchable::{SearchScheme, Searchable}, query_kind::QueryKind};
+
+use super::ranked::{answer, Ranked};
+
+/// A structure that can answer a lower bound query when told which ranks it falls in.
+pub trait SubrangeSearch: Ranked {
+    /// Rank of the first key `>= value`.
+    ///
+    /// The caller guarantees that all keys before `lo` are smaller than `value` and all keys
+    /// from `hi` on are not, so the result is in `lo..=hi`. Panics unless
+    /// `lo <= hi <= self.len()`.
+    fn lower_bound_in(&self, value: u32, lo: usize, hi: usize) -> usize;
+}
+
+/// Restricts searches in `S` to one of `2^BITS` buckets, selected by the top `BITS` bits of the key.
+///
+/// Keys are limited to the range that `S` allows.
+pub struct PrefixLut<S, const BITS: u32> {
+    inner: S,
+    // Rank of the first key of each bucket, plus the total length at the end.
+    table: Vec<u32>,
+    shift: u32,
+}
+
+impl<S: Searchable + SubrangeSearch, const BITS: u32> Searchable for PrefixLut<S, BITS> {
+    fn new(sorted_vals: &[u32]) -> Self {
+        assert!((1..32).contains(&BITS), "BITS must be between 1 and 31");
+        assert!(sorted_vals.len() < u32::MAX as usize, "Too many keys for the prefix table");
+
+        let max = sorted_vals.last().copied().unwrap_or(0);
+        let shift = (u32::BITS - max.leading_zeros()).saturating_sub(BITS);
+        let table: Vec<u32> = (0..=1 << BITS)
+            .map(|bucket| sorted_vals.partition_point(|&x| (x >> shift) < bucket) as u32)
+            .collect();
+
+        Self { inner: S::new(sorted_vals), table, shift }
+    }
+
+    fn get_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        vec!(&Self::search::<K>)
+    }
+
+    fn get_name(&self) -> String {
+        format!("{} ({} B table)", std::any::type_name::<Self>(), self.table_size())
+    }
+}
+
+impl<S: SubrangeSearch, const BITS: u32> PrefixLut<S, BITS> {
+    /// Size of the bucket table in bytes.
+    pub fn table_size(&self) -> usize {
+        self.table.len() * size_of::<u32>()
+    }
+
+    fn bucket(&self, value: u32) -> (usize, usize) {
+        let bucket = ((value >> self.shift) as usize).min((1 << BITS) - 1);
+        debug_assert!(bucket + 1 < self.table.len());
+        // SAFETY: the table has `2^BITS + 1` entries and the bucket was clamped below `2^BITS`.
+        unsafe {
+            (
+                *self.table.get_unchecked(bucket) as usize,
+                *self.table.get_unchecked(bucket + 1) as usize,
+            )
+        }
+    }
+
+    #[inline(never)]
+    fn search<K: QueryKind>(&self, value: u32) -> u32 {
+        let target = K::KIND.target(value);
+        let (lo, hi) = self.bucket(target);
+        answer::<K, _>(&self.inner, value, self.inner.lower_bound_in(target, lo, hi))
+    }
+}
+
+impl<S: SubrangeSearch, const BITS: u32> Ranked for PrefixLut<S, BITS> {
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn key_at(&self, rank: usize) -> u32 {
+        self.inner.key_at(rank)
+    }
+
+    fn lower_bound(&self, value: u32) -> usize {
+        let (lo, hi) = self.bucket(value);
+        self.inner.lower_bound_in(value, lo, hi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{query::query_kind::LowerBound, searches::{binary_search::SortedVec, ranked::Ranked, s_tree::STree}};
+
+    use super::*;
+
+    fn check<S: Searchable + SubrangeSearch>() {
+        for len in (0..300).chain([4912, 4913, 4914]) {
+            let vals: Vec<u32> = (0..len).map(|i| i * i + 7).collect();
+            let lut = PrefixLut::<S, 6>::new(&vals);
+            let end = vals.last().map_or(10, |&x| x + 10);
+            for q in (0..end).step_by(end as usize / 1000 + 1) {
+                let rank = vals.partition_point(|&x| x < q);
+                assert_eq!(lut.search::<LowerBound>(q), lut.inner.key_at(rank), "len {len}, query {q}");
+            }
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_sorted_vec() {
+        check::<SortedVec>();
+    }
+
+    fn rejects<S: Searchable + SubrangeSearch>(lo: usize, hi: usize) -> bool {
+        std::panic::catch_unwind(|| S::new(&[1, 2, 3]).lower_bound_in(2, lo, hi)).is_err()
+    }
+
+    #[test]
+    fn test_rejects_bad_subranges() {
+        for (lo, hi) in [(0, 4), (2, 1), (4, 4), (usize::MAX, 0)] {
+            assert!(rejects::<SortedVec>(lo, hi), "{lo}..{hi}");
+            assert!(rejects::<STree>(lo, hi), "{lo}..{hi}");
+        }
+        for (lo, hi) in [(0, 3), (1, 2), (3, 3)] {
+            assert!(!rejects::<SortedVec>(lo, hi), "{lo}..{hi}");
+            assert!(!rejects::<STree>(lo, hi), "{lo}..{hi}");
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_s_tree() {
+        check::<STree>();
+    }
+}
@@ -0,0 +1,165 @@
+use std::ops::Range;
+
+use crate::query::query_kind::{Kind, QueryKind};
+
+/// Width of the `[q, q + RANGE_WIDTH)` ranges counted by `RangeCount` queries.
+pub const RANGE_WIDTH: u32 = 1 << 20;
+
+/// A structure whose keys can be addressed by their rank in sorted order.
+///
+/// Keys may repeat. Equal keys have consecutive ranks, and `lower_bound` returns the rank of
+/// the first of them.
+pub trait Ranked {
+    /// Number of keys stored.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The key with the given rank, or the structure's sentinel if there is none. Keys are
+    /// below the sentinel, so it never stands for a key.
+    fn key_at(&self, rank: usize) -> u32;
+
+    /// Rank of the first key `>= value`, or `len()` if there is none.
+    fn lower_bound(&self, value: u32) -> usize;
+
+    /// Ranks of the first keys `>= values[i]`.
+    fn batch_lower_bound<const P: usize>(&self, values: &[u32; P]) -> [usize; P] {
+        values.map(|value| self.lower_bound(value))
+    }
+
+    /// Rank of the first key `> value`, or `len()` if there is none.
+    fn upper_bound(&self, value: u32) -> usize {
+        self.lower_bound(Kind::UpperBound.target(value))
+    }
+
+    /// The last key `<= value`, if any.
+    fn predecessor(&self, value: u32) -> Option<u32> {
+        self.upper_bound(value).checked_sub(1).map(|rank| self.key_at(rank))
+    }
+
+    /// Ranks of all keys equal to `value`.
+    fn equal_range(&self, value: u32) -> Range<usize> {
+        self.lower_bound(value)..self.upper_bound(value)
+    }
+
+    /// Number of keys equal to `value`.
+    fn count(&self, value: u32) -> usize {
+        self.equal_range(value).len()
+    }
+
+    /// Number of keys in `[lo, hi)`.
+    fn range_count(&self, lo: u32, hi: u32) -> usize {
+        self.lower_bound(hi).saturating_sub(self.lower_bound(lo))
+    }
+
+    /// All keys in `[lo, hi)`, in sorted order.
+    fn range(&self, lo: u32, hi: u32) -> impl Iterator<Item = u32> + '_ {
+        let start = self.lower_bound(lo);
+        let end = self.lower_bound(hi).max(start);
+        (start..end).map(|rank| self.key_at(rank))
+    }
+}
+
+/// Answers query `q` of kind `K`, given the rank of the lower bound of `K::KIND.target(q)`.
+///
+/// Like a missing successor, a missing predecessor is reported as the structure's sentinel.
+#[inline(always)]
+pub fn answer<K: QueryKind, S: Ranked>(s: &S, q: u32, rank: usize) -> u32 {
+    match K::KIND {
+        Kind::LowerBound | Kind::UpperBound => s.key_at(rank),
+        Kind::Predecessor => s.key_at(rank.checked_sub(1).unwrap_or(s.len())),
+        Kind::EqualRange => (s.upper_bound(q) - rank) as u32,
+        Kind::RangeCount => (q.checked_add(RANGE_WIDTH).map_or(s.len(), |hi| s.lower_bound(hi)) - rank) as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::{query::searchable::Searchable, searches::{b_eytzinger::BEytzinger, binary_search::SortedVec, dynamic::DynamicSTree, eytzinger::Eytzinger, prefix_lut::PrefixLut, s_tree::STree, s_tree_compressed::CompressedSTree, veb_tree::VebTree}};
+
+    use super::*;
+
+    fn check<S: Searchable + Ranked>() {
+        for len in (0..300).chain([4912, 4913, 4914]) {
+            let vals: Vec<u32> = (0..len).map(|i| 2 * i + 1).collect();
+            let s = S::new(&vals);
+            assert_eq!(s.len(), vals.len());
+            for (rank, &val) in vals.iter().enumerate().step_by(len as usize / 100 + 1) {
+                assert_eq!(s.key_at(rank), val);
+            }
+            for lo in (0..2 * len + 2).step_by(len as usize / 20 + 1) {
+                for hi in [lo, lo + 1, lo + 2, lo + 37, lo + 1000] {
+                    let expected: Vec<u32> = vals.iter().copied().filter(|v| (lo..hi).contains(v)).collect();
+                    assert_eq!(s.lower_bound(lo), vals.partition_point(|&x| x < lo));
+                    assert_eq!(s.range_count(lo, hi), expected.len(), "len {len}, range {lo}..{hi}");
+                    assert_eq!(s.range(lo, hi).collect::<Vec<_>>(), expected, "len {len}, range {lo}..{hi}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_sorted_vec() {
+        check::<SortedVec>();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_eytzinger() {
+        check::<Eytzinger>();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_s_tree() {
+        check::<STree>();
+    }
+
+    /// Sorted keys with long runs of equal values, so runs cross node and layer boundaries.
+    fn heavy_duplicates(rng: &mut StdRng, len: usize) -> Vec<u32> {
+        let distinct = [1, 2, 3, 10, 100][rng.random_range(0..5)];
+        let mut vals: Vec<u32> = (0..len).map(|_| 5 * rng.random_range(0..distinct) + 1).collect();
+        vals.sort();
+        vals
+    }
+
+    fn check_duplicates<S: Searchable + Ranked>() {
+        let mut rng = StdRng::seed_from_u64(39);
+        let mut lens = vec![0, 1, 15, 16, 17, 31, 32, 33, 271, 272, 273, 289, 4912, 4913];
+        lens.extend((0..20).map(|_| rng.random_range(0..3000)));
+        for len in lens {
+            for _ in 0..3 {
+                let vals = heavy_duplicates(&mut rng, len);
+                let s = S::new(&vals);
+                for (rank, &val) in vals.iter().enumerate() {
+                    assert_eq!(s.key_at(rank), val, "len {len}, rank {rank}");
+                }
+                for q in 0..vals.last().map_or(2, |&x| x + 2) {
+                    let first = vals.partition_point(|&x| x < q);
+                    let count = vals[first..].partition_point(|&x| x <= q);
+                    assert_eq!(s.lower_bound(q), first, "len {len}, query {q}");
+                    assert_eq!(s.count(q), count, "len {len}, query {q}");
+                    assert_eq!(s.equal_range(q), first..first + count, "len {len}, query {q}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_heavy_duplicates() {
+        check_duplicates::<SortedVec>();
+        check_duplicates::<Eytzinger>();
+        check_duplicates::<STree>();
+        check_duplicates::<CompressedSTree>();
+        check_duplicates::<VebTree>();
+        check_duplicates::<BEytzinger>();
+        check_duplicates::<PrefixLut<STree, 4>>();
+        check_duplicates::<DynamicSTree<64>>();
+    }
+}
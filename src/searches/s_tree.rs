@@ -2,30 +2,35 @@ use std::array::from_fn;
 
 use rand::Fill;
 
-use crate::{query::bench_search::{batched, Batched, SearchScheme, Searchable}, utils::prefetch_index};
+use crate::{query::{searchable::{batched, Batched, SearchScheme, Searchable}, query_kind::{Kind, QueryKind}}, utils::prefetch_index};
 
-use super::s_tree_node::STreeNode;
+use super::{from_sorted::FromSorted, persist::Persist, prefix_lut::SubrangeSearch, storage::Storage, ranked::{answer, Ranked, RANGE_WIDTH}, s_tree_node::STreeNode};
 
 pub const NODE_LEN: usize = 16;
 pub const MAX: u32 = i32::MAX as u32;
 
 
+/// A static B+ tree of `NODE_LEN`-key nodes, stored layer by layer.
+///
+/// Keys are compared as `i32`, so they must be at most `MAX`. `MAX` also pads the nodes and
+/// is what searches report when there is no answer.
 #[derive(Debug)]
 pub struct STree{
-    nodes: Vec<STreeNode>,
+    nodes: Storage<STreeNode>,
     offsets: Vec<usize>,
+    len: usize,
 }
 
 impl STree{
-    pub fn blocks_needed(key_amount: usize) -> usize{
+    pub(crate) fn blocks_needed(key_amount: usize) -> usize{
         key_amount.div_ceil(NODE_LEN)
     }
 
-    pub fn prev_keys(key_amount: usize) -> usize{
+    pub(crate) fn prev_keys(key_amount: usize) -> usize{
         Self::blocks_needed(key_amount).div_ceil(NODE_LEN + 1) * NODE_LEN
     }
 
-    pub fn height(key_amount: usize) -> usize{
+    pub(crate) fn height(key_amount: usize) -> usize{
         if key_amount <= NODE_LEN{
             1
         } else {
@@ -33,25 +38,19 @@ impl STree{
         }
     }
 
-    pub fn layer_size(mut key_amount: usize, h: usize, total_height: usize) -> usize {
+    pub(crate) fn layer_size(mut key_amount: usize, h: usize, total_height: usize) -> usize {
         for _ in h..total_height - 1 {
             key_amount = Self::prev_keys(key_amount);
         }
         key_amount
     }
 
-}
-
-impl Searchable for STree{
-    fn new(sorted_vals: &[u32]) -> Self {
-        let len = sorted_vals.len();
-
+    /// Number of nodes in each layer and where each layer starts, from the root down.
+    fn layout(len: usize) -> (Vec<usize>, Vec<usize>) {
         let height = Self::height(len);
         let layer_sizes: Vec<usize> = 
             (0..height).map(|h| Self::layer_size(len, h, height).div_ceil(NODE_LEN)).collect();
 
-        let n_blocks = layer_sizes.iter().sum::<usize>();
-
         let offsets: Vec<usize> = layer_sizes.iter()
             .scan(0, |state, layer| {
                 let res = *state;
@@ -59,12 +58,60 @@ impl Searchable for STree{
                 Some(res)
             })
         .collect();
+        (layer_sizes, offsets)
+    }
+
+    /// Key `i` of layer `h` above the leaves: the first key of the subtree to its right, or
+    /// MAX if that subtree holds no keys.
+    fn separator(nodes: &[STreeNode], offsets: &[usize], len: usize, h: usize, i: usize) -> u32 {
+        let height = offsets.len();
+        let mut leaf_node_idx = i / NODE_LEN * (NODE_LEN + 1) + 1 + i % NODE_LEN;
+        for _ in h..height - 2{
+            leaf_node_idx *= NODE_LEN + 1;
+        }
+
+        if leaf_node_idx * NODE_LEN < len {
+            nodes[offsets[height - 1] + leaf_node_idx].keys[0]
+        } else {
+            MAX
+        }
+    }
+
+    /// Whether the nodes are exactly what `from_exact` builds from the keys in the leaves.
+    ///
+    /// The searches rely on this to stay inside the tree: a key above MAX or a wrong
+    /// separator sends them to children that don't exist.
+    fn is_valid(nodes: &[STreeNode], offsets: &[usize], len: usize) -> bool {
+        let height = offsets.len();
+        // The leaves run to the end of the array, including the sentinel node.
+        let leaves = &nodes[offsets[height - 1]..];
+        let key = |i: usize| leaves[i / NODE_LEN].keys[i % NODE_LEN];
+
+        let sorted = (0..len).all(|i| key(i) <= MAX && (i == 0 || key(i - 1) <= key(i)));
+        let padded = (len..leaves.len() * NODE_LEN).all(|i| key(i) == MAX);
+        let separated = (0..height - 1).all(|h| {
+            (0..(offsets[h + 1] - offsets[h]) * NODE_LEN)
+                .all(|i| nodes[offsets[h] + i / NODE_LEN].keys[i % NODE_LEN] == Self::separator(nodes, offsets, len, h, i))
+        });
+        sorted && padded && separated
+    }
+}
 
-        let mut nodes = vec![STreeNode{keys: [MAX; NODE_LEN]}; n_blocks];
+impl FromSorted for STree{
+    const MAX_KEY: u32 = MAX;
 
+    fn from_exact(len: usize, keys: impl Iterator<Item = u32>) -> Self {
+        let height = Self::height(len);
+        let (layer_sizes, offsets) = Self::layout(len);
+        let n_blocks = layer_sizes.iter().sum::<usize>();
+
+        // The extra node at the end lets a search that runs past a full last leaf read MAX.
+        let mut nodes = vec![STreeNode{keys: [MAX; NODE_LEN]}; n_blocks + 1];
+
+        // The leaves are filled as the keys arrive; the layers above only read the leaves.
         let leaf_layer_offset = offsets[height - 1];
-        for (i, val) in sorted_vals.iter().enumerate(){
-            nodes[leaf_layer_offset + i / NODE_LEN].keys[i % NODE_LEN] = *val;
+        for (i, val) in keys.take(len).enumerate(){
+            nodes[leaf_layer_offset + i / NODE_LEN].keys[i % NODE_LEN] = val;
         };
 
         if len / NODE_LEN < layer_sizes[height - 1]{
@@ -75,118 +122,228 @@ impl Searchable for STree{
             let offset = offsets[h];
 
             for i in 0..layer_sizes[h] * NODE_LEN{
-                let node_idx = i / NODE_LEN;
-                let key_idx = i % NODE_LEN;
-
-                let mut leaf_node_idx = node_idx;
-                leaf_node_idx = leaf_node_idx * (NODE_LEN + 1) + 1 + key_idx;
-                for _ in h..height - 2{
-                    leaf_node_idx *= NODE_LEN + 1;
-                }
-
-                nodes[offset + node_idx].keys[key_idx] = if leaf_node_idx * NODE_LEN < len {
-                    nodes[leaf_layer_offset + leaf_node_idx].keys[0]
-                } else {
-                    MAX
-                };
+                nodes[offset + i / NODE_LEN].keys[i % NODE_LEN] = Self::separator(&nodes, &offsets, len, h, i);
             };
         };
 
-        Self {offsets, nodes}
+        Self {offsets, nodes: nodes.into(), len}
+    }
+}
+
+impl Searchable for STree{
+    fn new(sorted_vals: &[u32]) -> Self {
+        assert!(sorted_vals.last().is_none_or(|&key| key <= MAX), "Keys must be at most MAX");
+        Self::from_exact(sorted_vals.len(), sorted_vals.iter().copied())
     }
 
-    fn get_funcs() -> Vec<&'static dyn SearchScheme<Self>> {
-        let batch_2 = Box::leak(Box::new(batched(Self::batch::<2>)));
-        let batch_4 = Box::leak(Box::new(batched(Self::batch::<4>)));
-        let batch_8 = Box::leak(Box::new(batched(Self::batch::<8>)));
-        let batch_16 = Box::leak(Box::new(batched(Self::batch::<16>)));
-        let batch_32 = Box::leak(Box::new(batched(Self::batch::<32>)));
-        let batch_64 = Box::leak(Box::new(batched(Self::batch::<64>)));
-        let batch_128 = Box::leak(Box::new(batched(Self::batch::<128>)));
-        let batch_128_prefetch = Box::leak(Box::new(batched(Self::batch_prefetch::<128>)));
-        //vec!(&Self::search_popcnt, batch_2, batch_4, batch_8, batch_16, batch_32, batch_64, batch_128)
+    fn get_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        let batch_128 = Box::leak(Box::new(batched(Self::batch::<128, K>)));
+        let batch_128_prefetch = Box::leak(Box::new(batched(Self::batch_prefetch::<128, K>)));
+        if K::KIND == Kind::RangeCount {
+            let range_count_128 = Box::leak(Box::new(batched(Self::batch_range_count::<128>)));
+            return vec!(batch_128, batch_128_prefetch, range_count_128);
+        }
         vec!(batch_128, batch_128_prefetch)
     }
+
+    fn all_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        let mut funcs: Vec<&'static dyn SearchScheme<Self>> = vec!(
+            &Self::search_linear::<K>,
+            &Self::search_linear_count::<K>,
+            &Self::search_popcnt::<K>,
+            Box::leak(Box::new(batched(Self::batch::<2, K>))),
+            Box::leak(Box::new(batched(Self::batch::<4, K>))),
+            Box::leak(Box::new(batched(Self::batch::<8, K>))),
+            Box::leak(Box::new(batched(Self::batch::<16, K>))),
+            Box::leak(Box::new(batched(Self::batch::<32, K>))),
+            Box::leak(Box::new(batched(Self::batch::<64, K>))),
+        );
+        #[cfg(feature = "nightly")]
+        funcs.push(&Self::search_manual_simd::<K>);
+        funcs.extend(Self::get_funcs::<K>());
+        funcs
+    }
+}
+
+impl Ranked for STree {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn key_at(&self, rank: usize) -> u32 {
+        if rank < self.len {
+            self.key(self.offsets.last().unwrap() + rank / NODE_LEN, rank % NODE_LEN)
+        } else {
+            MAX
+        }
+    }
+
+    fn lower_bound(&self, value: u32) -> usize {
+        self.lower_bound_in(value, 0, self.len)
+    }
+
+    /// Searches level by level for all values at once.
+    fn batch_lower_bound<const P: usize>(&self, values: &[u32; P]) -> [usize; P] {
+        let values = values.map(|v| v.min(MAX));
+        let mut k = [0; P];
+        for (&o, &o2) in self.offsets.iter().zip(&self.offsets[1..]) {
+            for i in 0..P{
+                let jump_to = self.node(o + k[i]).find_popcnt(values[i]);
+                k[i] = k[i] * (NODE_LEN + 1) + jump_to;
+                prefetch_index(&self.nodes, o2 + k[i])
+            }
+        }
+
+        let o = self.offsets.last().unwrap();
+        from_fn(|i| k[i] * NODE_LEN + self.node(o + k[i]).find_popcnt(values[i]))
+    }
+}
+
+impl Persist for STree {
+    const STRUCTURE: u32 = 2;
+    const NODE_WIDTH: u32 = NODE_LEN as u32;
+    type Node = STreeNode;
+
+    fn parts(&self) -> (&[usize], &[STreeNode]) {
+        (&self.offsets, &self.nodes)
+    }
+
+    fn from_parts(len: usize, offsets: Vec<usize>, nodes: Storage<STreeNode>) -> Option<Self> {
+        let (layer_sizes, expected) = Self::layout(len);
+        let n_blocks = layer_sizes.iter().sum::<usize>();
+        (offsets == expected && nodes.len() == n_blocks + 1 && Self::is_valid(&nodes, &offsets, len))
+            .then_some(Self { nodes, offsets, len })
+    }
+}
+
+impl SubrangeSearch for STree {
+    fn lower_bound_in(&self, value: u32, lo: usize, hi: usize) -> usize {
+        assert!(lo <= hi && hi <= self.len(), "Subrange {lo}..{hi} out of bounds for {} keys", self.len());
+        if lo == hi {
+            return lo;
+        }
+        // Keys are compared as i32, so anything past MAX has to be clamped.
+        let value = value.min(MAX);
+
+        // Start from the lowest node whose subtree covers all leaves of the range.
+        let height = self.offsets.len();
+        let mut layer = height - 1;
+        let mut node_idx = lo / NODE_LEN;
+        let mut last_idx = (hi - 1) / NODE_LEN;
+        while node_idx != last_idx {
+            node_idx /= NODE_LEN + 1;
+            last_idx /= NODE_LEN + 1;
+            layer -= 1;
+        }
+
+        for offset in &self.offsets[layer..height - 1] {
+            let jump_to = self.node(offset + node_idx).find_popcnt(value);
+            node_idx = node_idx * (NODE_LEN + 1) + jump_to;
+        }
+
+        let last = self.offsets.last().unwrap();
+        node_idx * NODE_LEN + self.node(last + node_idx).find_popcnt(value)
+    }
 }
 
 impl STree {
+    /// The internal nodes, the offsets of their layers, and the leaves followed by the
+    /// sentinel node.
+    pub(crate) fn layers(&self) -> (&[STreeNode], &[usize], &[STreeNode]) {
+        let height = self.offsets.len();
+        let (internal, leaves) = self.nodes.split_at(self.offsets[height - 1]);
+        (internal, &self.offsets[..height - 1], leaves)
+    }
+
     fn node(&self, node_idx: usize) -> &STreeNode {
+        debug_assert!(node_idx < self.nodes.len(), "node {node_idx} out of bounds");
+        // SAFETY: values are at most MAX, so a search never moves past a MAX key into a child
+        // that does not exist, and a full last leaf is followed by the sentinel node.
         unsafe { self.nodes.get_unchecked(node_idx) }
     }
 
     fn key(&self, node_idx: usize, key_idx: usize) -> u32 {
-        unsafe { *self.nodes.get_unchecked(node_idx).keys.get_unchecked(key_idx) }
+        self.node(node_idx).keys[key_idx % NODE_LEN]
     }
 
 
-    fn search_with_find_impl(&self, value: u32, find: impl Fn(&STreeNode, u32) -> usize) -> u32{
+    /// Turns the rank of the lower bound of `K::KIND.target(value)` into the answer.
+    fn finish<K: QueryKind>(&self, value: u32, rank: usize) -> u32 {
+        match K::KIND {
+            Kind::LowerBound | Kind::UpperBound => {
+                let last = self.offsets.last().unwrap();
+                self.key(last + rank / NODE_LEN, rank % NODE_LEN)
+            }
+            _ => answer::<K, _>(self, value, rank),
+        }
+    }
+
+    fn search_with_find_impl<K: QueryKind>(&self, value: u32, find: impl Fn(&STreeNode, u32) -> usize) -> u32{
+        let target = K::KIND.target(value).min(MAX);
         let mut node_idx = 0;
-        for [offset, _] in self.offsets.array_windows(){
-            let jump_to = find(self.node(offset + node_idx), value);
+        for offset in &self.offsets[..self.offsets.len() - 1]{
+            let jump_to = find(self.node(offset + node_idx), target);
             node_idx = node_idx * (NODE_LEN + 1) + jump_to;
         }
 
         let last = self.offsets.last().unwrap();
         let node = self.node(last + node_idx);
-        let key_idx = find(node, value);
-        self.key(last + node_idx + key_idx / NODE_LEN, key_idx % NODE_LEN)
+        let key_idx = find(node, target);
+        self.finish::<K>(value, node_idx * NODE_LEN + key_idx)
     }
 
     #[inline(never)]
-    fn search_linear(&self, value: u32) -> u32{
-        self.search_with_find_impl(value, STreeNode::find_linear)
+    fn search_linear<K: QueryKind>(&self, value: u32) -> u32{
+        self.search_with_find_impl::<K>(value, STreeNode::find_linear)
     }
 
     #[inline(never)]
-    fn search_linear_count(&self, value: u32) -> u32{
-        self.search_with_find_impl(value, STreeNode::find_linear_count)
+    fn search_linear_count<K: QueryKind>(&self, value: u32) -> u32{
+        self.search_with_find_impl::<K>(value, STreeNode::find_linear_count)
     }
 
+    #[cfg(feature = "nightly")]
     #[inline(never)]
-    fn search_manual_simd(&self, value: u32) -> u32 {
-        self.search_with_find_impl(value, STreeNode::find_simd)
+    fn search_manual_simd<K: QueryKind>(&self, value: u32) -> u32 {
+        self.search_with_find_impl::<K>(value, STreeNode::find_simd)
     }
 
     #[inline(never)]
-    fn search_popcnt(&self, value: u32) -> u32 {
-        #[cfg(not(target_feature = "avx2"))]
-        compile_error!("AVX2 support is required to compile this program");
-        self.search_with_find_impl(value, |node, val| unsafe { node.find_popcnt(val)})
+    fn search_popcnt<K: QueryKind>(&self, value: u32) -> u32 {
+        self.search_with_find_impl::<K>(value, STreeNode::find_popcnt)
     }
 
     #[inline(never)]
-    fn batch<const P: usize>(&self, values: &[u32; P]) -> [u32; P]{
+    fn batch<const P: usize, K: QueryKind>(&self, values: &[u32; P]) -> [u32; P]{
+        let targets = values.map(|v| K::KIND.target(v).min(MAX));
         let mut k = [0; P];
-        for [o, _] in self.offsets.array_windows() {
+        for o in &self.offsets[..self.offsets.len() - 1] {
             for i in 0..P{
-                let jump_to = self.node(o + k[i]).find_popcnt(values[i]);
+                let jump_to = self.node(o + k[i]).find_popcnt(targets[i]);
                 k[i] = k[i] * (NODE_LEN + 1) + jump_to;
             }
         }
 
         let o = self.offsets.last().unwrap();
         from_fn(|i| {
-            let idx = self.node(o + k[i]).find_popcnt(values[i]);
-            self.key(o + k[i] + idx / NODE_LEN, idx % NODE_LEN)
+            let idx = self.node(o + k[i]).find_popcnt(targets[i]);
+            self.finish::<K>(values[i], k[i] * NODE_LEN + idx)
         })
     }
 
     #[inline(never)]
-    fn batch_prefetch<const P: usize>(&self, values: &[u32; P]) -> [u32; P]{
-        let mut k = [0; P];
-        for [o, o2] in self.offsets.array_windows() {
-            for i in 0..P{
-                let jump_to = self.node(o + k[i]).find_popcnt(values[i]);
-                k[i] = k[i] * (NODE_LEN + 1) + jump_to;
-                prefetch_index(&self.nodes, o2 + k[i])
-            }
-        }
+    fn batch_prefetch<const P: usize, K: QueryKind>(&self, values: &[u32; P]) -> [u32; P]{
+        let ranks = self.batch_lower_bound(&values.map(|v| K::KIND.target(v)));
+        from_fn(|i| self.finish::<K>(values[i], ranks[i]))
+    }
+}
 
-        let o = self.offsets.last().unwrap();
-        from_fn(|i| {
-            let idx = self.node(o + k[i]).find_popcnt(values[i]);
-            self.key(o + k[i] + idx / NODE_LEN, idx % NODE_LEN)
-        })
+impl STree {
+    #[inline(never)]
+    fn batch_range_count<const P: usize>(&self, values: &[u32; P]) -> [u32; P]{
+        let lo = self.batch_lower_bound(values);
+        let hi = self.batch_lower_bound(&values.map(|v| v.saturating_add(RANGE_WIDTH)));
+        from_fn(|i| (hi[i] - lo[i]) as u32)
     }
 }
 
@@ -195,10 +352,16 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_tree(){
-        let arr = [0, 1, 2, 3, 4, 5, 6, 7 ,8 ,9 ,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31,32, 33, 34, 35, 36 ,37,38,39,40,41,42,43,44,45,46,47,48,49,50,51,52,53,54,55,56,57,58,59,60,61,62,63,64,65,66,67,68,69,70,71,72,73,74,75,76,77,78,79,80,81,82,83,84,85,86,87,88];
+    fn test_tree() {
+        let arr: Vec<u32> = (0..89).collect();
         let tree = STree::new(&arr);
-        println!("{:?}", tree);
-        assert_eq!(1, 2);
+        // Six leaves under one root, whose keys are the first keys of leaves one to five.
+        assert_eq!(tree.offsets, [0, 1]);
+        assert_eq!(tree.nodes.len(), 1 + 6 + 1);
+        assert_eq!(tree.nodes[0].keys[..6], [16, 32, 48, 64, 80, MAX]);
+        assert!(tree.nodes[6].keys[9..].iter().all(|&key| key == MAX));
+        for q in 0..=90 {
+            assert_eq!(tree.lower_bound(q), (q as usize).min(arr.len()), "query {q}");
+        }
     }
 }
@@ -0,0 +1,270 @@
+use std::{array::from_fn, hint::select_unpredictable};
+
+#[cfg(feature = "nightly")]
+use std::simd::{cmp::SimdPartialOrd, Simd};
+#[cfg(all(not(feature = "nightly"), target_arch = "x86_64", target_feature = "avx2"))]
+use std::arch::x86_64::{__m256i, _mm256_cmpgt_epi16, _mm256_load_si256, _mm256_movemask_epi8, _mm256_packs_epi16, _mm256_set1_epi16, _mm256_xor_si256};
+
+use crate::{query::{searchable::{batched, SearchScheme, Searchable}, query_kind::{Kind, QueryKind}}, utils::prefetch_index};
+
+use super::{ranked::{answer, Ranked}, s_tree::{MAX, NODE_LEN}, s_tree_node::STreeNode};
+
+/// Keys per leaf.
+pub const LEAF_LEN: usize = 32;
+// Marks a leaf stored at full width; a compressed leaf always starts with delta 0.
+const WIDE: u16 = u16::MAX;
+// Largest span a compressed leaf can hold, so a clamped query delta is above all of them.
+const MAX_SPAN: u32 = WIDE as u32 - 1;
+
+/// 32 keys as 16-bit deltas to the first key of the leaf.
+///
+/// The first key is not stored: it is the separator that led the search to this leaf.
+/// Leaves whose keys span more than `MAX_SPAN`, and the partial last leaf, are stored at full
+/// width instead; their first delta is `WIDE` and the next two hold their index.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, align(64))]
+pub struct CompressedLeaf {
+    deltas: [u16; LEAF_LEN],
+}
+
+impl CompressedLeaf {
+    fn wide_index(&self) -> Option<usize> {
+        (self.deltas[0] == WIDE).then(|| self.deltas[1] as usize | (self.deltas[2] as usize) << 16)
+    }
+
+    /// Number of keys whose delta is `< delta`.
+    #[cfg(feature = "nightly")]
+    #[inline(always)]
+    pub fn count_below(&self, delta: u16) -> usize {
+        let deltas = Simd::<u16, LEAF_LEN>::from_array(self.deltas);
+        deltas.simd_lt(Simd::splat(delta)).to_bitmask().count_ones() as usize
+    }
+
+    /// Number of keys whose delta is `< delta`. AVX2 only compares signed lanes, so both
+    /// sides are shifted by `0x8000` first.
+    #[cfg(all(not(feature = "nightly"), target_arch = "x86_64", target_feature = "avx2"))]
+    #[inline(always)]
+    pub fn count_below(&self, delta: u16) -> usize {
+        unsafe {
+            let ptr = self.deltas.as_ptr() as *const __m256i;
+            let bias = _mm256_set1_epi16(i16::MIN);
+            let delta = _mm256_xor_si256(_mm256_set1_epi16(delta as i16), bias);
+            let low = _mm256_xor_si256(_mm256_load_si256(ptr), bias);
+            let high = _mm256_xor_si256(_mm256_load_si256(ptr.add(1)), bias);
+            let merged = _mm256_packs_epi16(_mm256_cmpgt_epi16(delta, low), _mm256_cmpgt_epi16(delta, high));
+            _mm256_movemask_epi8(merged).count_ones() as usize
+        }
+    }
+
+    /// Number of keys whose delta is `< delta`, for targets without AVX2.
+    #[cfg(all(not(feature = "nightly"), not(all(target_arch = "x86_64", target_feature = "avx2"))))]
+    #[inline(always)]
+    pub fn count_below(&self, delta: u16) -> usize {
+        self.deltas.iter().filter(|&&d| d < delta).count()
+    }
+}
+
+/// An S-tree whose leaves hold 32 keys in one cache line, as 16-bit deltas.
+///
+/// The internal nodes are `STreeNode`s as in `STree`, with the leaves counted as the
+/// bottom layer. As in `STree`, keys must be at most `MAX`.
+#[derive(Debug)]
+pub struct CompressedSTree {
+    internal: Vec<STreeNode>,
+    offsets: Vec<usize>,
+    leaves: Vec<CompressedLeaf>,
+    // Full width copies of the leaves that could not be compressed, padded with MAX.
+    wide: Vec<[STreeNode; 2]>,
+    first: u32,
+    len: usize,
+}
+
+impl Searchable for CompressedSTree {
+    fn new(sorted_vals: &[u32]) -> Self {
+        assert!(sorted_vals.last().is_none_or(|&key| key <= MAX), "Keys must be at most MAX");
+        let len = sorted_vals.len();
+        let n_leaves = len.div_ceil(LEAF_LEN).max(1);
+
+        let mut layer_sizes = vec!();
+        let mut children = n_leaves;
+        while children > 1 {
+            children = children.div_ceil(NODE_LEN + 1);
+            layer_sizes.push(children);
+        }
+        layer_sizes.reverse();
+        let offsets: Vec<usize> = layer_sizes.iter()
+            .scan(0, |state, layer| {
+                let res = *state;
+                *state += layer;
+                Some(res)
+            })
+            .collect();
+
+        let mut wide = vec!();
+        let leaves = (0..n_leaves).map(|i| {
+            let keys = &sorted_vals[(i * LEAF_LEN).min(len)..((i + 1) * LEAF_LEN).min(len)];
+            if keys.len() == LEAF_LEN && keys[LEAF_LEN - 1] - keys[0] <= MAX_SPAN {
+                CompressedLeaf { deltas: from_fn(|j| (keys[j] - keys[0]) as u16) }
+            } else {
+                let idx = wide.len();
+                let key = |j: usize| keys.get(j).copied().unwrap_or(MAX);
+                wide.push([STreeNode { keys: from_fn(key) }, STreeNode { keys: from_fn(|j| key(j + NODE_LEN)) }]);
+                let mut deltas = [0; LEAF_LEN];
+                deltas[..3].copy_from_slice(&[WIDE, idx as u16, (idx >> 16) as u16]);
+                CompressedLeaf { deltas }
+            }
+        }).collect();
+
+        let height = layer_sizes.len();
+        let mut internal = vec![STreeNode { keys: [MAX; NODE_LEN] }; layer_sizes.iter().sum()];
+        for h in 0..height {
+            for node_idx in 0..layer_sizes[h] {
+                for key_idx in 0..NODE_LEN {
+                    // The first key of the leftmost leaf below child `key_idx + 1`.
+                    let mut leaf_idx = node_idx * (NODE_LEN + 1) + key_idx + 1;
+                    for _ in h + 1..height {
+                        leaf_idx *= NODE_LEN + 1;
+                    }
+                    if let Some(&key) = sorted_vals.get(leaf_idx * LEAF_LEN) {
+                        internal[offsets[h] + node_idx].keys[key_idx] = key;
+                    }
+                }
+            }
+        }
+
+        Self { internal, offsets, leaves, wide, first: sorted_vals.first().copied().unwrap_or(0), len }
+    }
+
+    fn get_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        let batch_128_prefetch = Box::leak(Box::new(batched(Self::batch_prefetch::<128, K>)));
+        vec!(batch_128_prefetch)
+    }
+}
+
+impl CompressedSTree {
+    fn node(&self, node_idx: usize) -> &STreeNode {
+        debug_assert!(node_idx < self.internal.len(), "node {node_idx} out of bounds");
+        // SAFETY: as in `STree`, searches only descend into children that exist.
+        unsafe { self.internal.get_unchecked(node_idx) }
+    }
+
+    fn leaf(&self, leaf_idx: usize) -> &CompressedLeaf {
+        debug_assert!(leaf_idx < self.leaves.len(), "leaf {leaf_idx} out of bounds");
+        // SAFETY: the internal nodes only lead to leaves that exist.
+        unsafe { self.leaves.get_unchecked(leaf_idx) }
+    }
+
+    /// Number of keys of leaf `leaf_idx` that are `< value`, given its first key.
+    #[inline(always)]
+    fn count_in_leaf(&self, leaf_idx: usize, fence: u32, value: u32) -> usize {
+        let leaf = self.leaf(leaf_idx);
+        match leaf.wide_index() {
+            None => leaf.count_below(value.saturating_sub(fence).min(WIDE as u32) as u16),
+            Some(idx) => {
+                let [low, high] = &self.wide[idx];
+                low.find_popcnt(value) + high.find_popcnt(value)
+            }
+        }
+    }
+
+    /// Moves from internal node `node` to child `jump_to`, keeping track of the child's first key.
+    #[inline(always)]
+    fn descend(node: &STreeNode, node_idx: usize, fence: u32, value: u32) -> (usize, u32) {
+        let jump_to = node.find_popcnt(value);
+        let fence = select_unpredictable(jump_to > 0, node.keys[jump_to.saturating_sub(1)], fence);
+        (node_idx * (NODE_LEN + 1) + jump_to, fence)
+    }
+
+    /// The first key of leaf `leaf_idx`: the separator of the lowest ancestor it is not the
+    /// leftmost leaf of.
+    fn fence(&self, mut idx: usize) -> u32 {
+        for &offset in self.offsets.iter().rev() {
+            let (parent, child) = (idx / (NODE_LEN + 1), idx % (NODE_LEN + 1));
+            if child > 0 {
+                return self.node(offset + parent).keys[child - 1];
+            }
+            idx = parent;
+        }
+        self.first
+    }
+
+    #[inline(never)]
+    fn batch_prefetch<const P: usize, K: QueryKind>(&self, values: &[u32; P]) -> [u32; P] {
+        let ranks = self.batch_lower_bound(&values.map(|v| K::KIND.target(v)));
+        from_fn(|i| match K::KIND {
+            Kind::LowerBound | Kind::UpperBound => self.key_at(ranks[i]),
+            _ => answer::<K, _>(self, values[i], ranks[i]),
+        })
+    }
+}
+
+impl Ranked for CompressedSTree {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn key_at(&self, rank: usize) -> u32 {
+        if rank >= self.len {
+            return MAX;
+        }
+        let (leaf_idx, key_idx) = (rank / LEAF_LEN, rank % LEAF_LEN);
+        let leaf = self.leaf(leaf_idx);
+        match leaf.wide_index() {
+            None => self.fence(leaf_idx) + leaf.deltas[key_idx] as u32,
+            Some(idx) => self.wide[idx][key_idx / NODE_LEN].keys[key_idx % NODE_LEN],
+        }
+    }
+
+    fn lower_bound(&self, value: u32) -> usize {
+        self.batch_lower_bound(&[value])[0]
+    }
+
+    /// Searches level by level for all values at once.
+    fn batch_lower_bound<const P: usize>(&self, values: &[u32; P]) -> [usize; P] {
+        // Keys are compared as i32, so anything past MAX has to be clamped.
+        let values = values.map(|v| v.min(MAX));
+        let mut k = [0; P];
+        let mut fences = [self.first; P];
+        for (h, &o) in self.offsets.iter().enumerate() {
+            for i in 0..P {
+                (k[i], fences[i]) = Self::descend(self.node(o + k[i]), k[i], fences[i], values[i]);
+                match self.offsets.get(h + 1) {
+                    Some(&next) => prefetch_index(&self.internal, next + k[i]),
+                    None => prefetch_index(&self.leaves, k[i]),
+                }
+            }
+        }
+        from_fn(|i| k[i] * LEAF_LEN + self.count_in_leaf(k[i], fences[i], values[i]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_below() {
+        let leaf = CompressedLeaf { deltas: from_fn(|j| [0, 1, 0x7fff, 0x8000, 0x8001, MAX_SPAN as u16][j % 6]) };
+        for delta in [0, 1, 2, 0x7fff, 0x8000, 0x8001, 0x8002, MAX_SPAN as u16, WIDE] {
+            assert_eq!(leaf.count_below(delta), leaf.deltas.iter().filter(|&&d| d < delta).count(), "delta {delta:#x}");
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_mixed_spans() {
+        // Dense runs that compress, separated by gaps too wide for a leaf.
+        for len in [0, 1, 31, 32, 33, 32 * 17, 32 * 17 + 1, 20_000] {
+            let vals: Vec<u32> = (0..len).map(|i| (i / 40) * 100_000 + (i % 40) * 3).collect();
+            let tree = CompressedSTree::new(&vals);
+            assert_eq!(tree.len(), vals.len());
+            for (rank, &val) in vals.iter().enumerate() {
+                assert_eq!(tree.key_at(rank), val, "len {len}, rank {rank}");
+            }
+            let queries = vals.iter().flat_map(|&v| [v.saturating_sub(1), v, v + 1]).chain([0, MAX, u32::MAX]);
+            for q in queries {
+                assert_eq!(tree.lower_bound(q), vals.partition_point(|&x| x < q.min(MAX)), "len {len}, query {q}");
+            }
+        }
+    }
+}
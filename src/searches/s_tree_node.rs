@@ -1,9 +1,15 @@
-use std::{arch::x86_64::{__m256, _mm256_movemask_epi8, _mm256_packs_epi32, _popcnt32}, mem::transmute, simd::{cmp::SimdPartialOrd, Simd}};
+#[cfg(feature = "nightly")]
+use std::{arch::x86_64::{_mm256_movemask_epi8, _mm256_packs_epi32, _popcnt32}, simd::{cmp::SimdPartialOrd, num::SimdUint, Simd}};
+#[cfg(all(not(feature = "nightly"), target_arch = "x86_64", target_feature = "avx2"))]
+use std::arch::x86_64::{__m256i, _mm256_cmpgt_epi32, _mm256_load_si256, _mm256_movemask_epi8, _mm256_packs_epi32, _mm256_set1_epi32};
 
 use super::s_tree::NODE_LEN;
 
+#[cfg(all(feature = "nightly", not(target_feature = "avx2")))]
+compile_error!("The `nightly` feature requires AVX2 support");
+
 #[derive(Clone, Copy, Debug)]
-#[repr(align(64))]
+#[repr(C, align(64))]
 pub struct STreeNode{
     pub keys: [u32; NODE_LEN],
 }
@@ -31,6 +37,7 @@ impl STreeNode{
         count
     }
 
+    #[cfg(feature = "nightly")]
     #[inline(always)]
     pub fn find_simd(&self, value: u32) -> usize {
         let data: Simd<u32, 16> = Simd::from_slice(&self.keys[0..16]);
@@ -39,21 +46,63 @@ impl STreeNode{
         mask.first_set().unwrap_or(16)
     }
 
-
+    /// Number of keys `< value`. Keys and `value` are compared as `i32`, so both have to
+    /// be at most `MAX`.
+    #[cfg(feature = "nightly")]
     #[inline(always)]
-    /// # Safety
-    ///
     pub fn find_popcnt(&self, value: u32) -> usize{
         let low: Simd<u32, 8> = Simd::from_slice(&self.keys[0..8]);
         let high: Simd<u32, 8> = Simd::from_slice(&self.keys[8..16]);
         let value_simd = Simd::<i32, 8>::splat(value as i32);
         unsafe {
-            use std::mem::transmute as t;
-            let mask_low = value_simd.simd_gt(t(low));
-            let mask_high = value_simd.simd_gt(t(high));
-            let merged = _mm256_packs_epi32(t(mask_low), t(mask_high));
+            let mask_low = value_simd.simd_gt(low.cast::<i32>()).to_simd();
+            let mask_high = value_simd.simd_gt(high.cast::<i32>()).to_simd();
+            let merged = _mm256_packs_epi32(mask_low.into(), mask_high.into());
             let mask: i32 = _mm256_movemask_epi8(merged);
-            _popcnt32(mask as i32) as usize / 2
+            _popcnt32(mask) as usize / 2
+        }
+    }
+
+    /// Number of keys `< value`. Keys and `value` are compared as `i32`, so both have to
+    /// be at most `MAX`.
+    #[cfg(all(not(feature = "nightly"), target_arch = "x86_64", target_feature = "avx2"))]
+    #[inline(always)]
+    pub fn find_popcnt(&self, value: u32) -> usize{
+        unsafe {
+            let ptr = self.keys.as_ptr() as *const __m256i;
+            let value_simd = _mm256_set1_epi32(value as i32);
+            let mask_low = _mm256_cmpgt_epi32(value_simd, _mm256_load_si256(ptr));
+            let mask_high = _mm256_cmpgt_epi32(value_simd, _mm256_load_si256(ptr.add(1)));
+            let merged = _mm256_packs_epi32(mask_low, mask_high);
+            _mm256_movemask_epi8(merged).count_ones() as usize / 2
+        }
+    }
+
+    /// Number of keys `< value`, for targets without AVX2.
+    #[cfg(all(not(feature = "nightly"), not(all(target_arch = "x86_64", target_feature = "avx2"))))]
+    #[inline(always)]
+    pub fn find_popcnt(&self, value: u32) -> usize{
+        self.keys.iter().filter(|&&key| (key as i32) < value as i32).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::searches::s_tree::MAX;
+
+    #[test]
+    fn test_finds_agree() {
+        let node = STreeNode { keys: std::array::from_fn(|i| 3 * i as u32 + 1) };
+        let full = STreeNode { keys: [MAX; NODE_LEN] };
+        for value in (0..3 * NODE_LEN as u32 + 3).chain([MAX - 1, MAX]) {
+            for node in [&node, &full] {
+                let expected = node.find_linear_count(value);
+                assert_eq!(node.find_linear(value), expected, "value {value}");
+                assert_eq!(node.find_popcnt(value), expected, "value {value}");
+                #[cfg(feature = "nightly")]
+                assert_eq!(node.find_simd(value), expected, "value {value}");
+            }
         }
     }
 }
@@ -0,0 +1,76 @@
+use std::{fmt, marker::PhantomData, ops::Deref, slice};
+
+use memmap2::Mmap;
+
+use super::s_tree_node::STreeNode;
+
+/// Plain data that can be viewed as, and rebuilt from, raw bytes.
+///
+/// # Safety
+///
+/// Implementors must have no padding, no pointers and no invalid bit patterns.
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for STreeNode {}
+
+pub(crate) fn as_bytes<T: Pod>(items: &[T]) -> &[u8] {
+    // SAFETY: `T: Pod` has no padding, so all of its bytes are initialized.
+    unsafe { slice::from_raw_parts(items.as_ptr() as *const u8, size_of_val(items)) }
+}
+
+pub(crate) fn as_bytes_mut<T: Pod>(items: &mut [T]) -> &mut [u8] {
+    // SAFETY: `T: Pod` has no invalid bit patterns, so any bytes written are a valid `T`.
+    unsafe { slice::from_raw_parts_mut(items.as_mut_ptr() as *mut u8, size_of_val(items)) }
+}
+
+/// Backing memory of a search structure: either built in memory or mapped from a file.
+pub enum Storage<T: Pod> {
+    Owned(Vec<T>),
+    Mapped(Mapped<T>),
+}
+
+/// A `[T]` inside a memory-mapped file.
+pub struct Mapped<T: Pod> {
+    map: Mmap,
+    offset: usize,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> Mapped<T> {
+    /// Views `len` items starting at byte `offset` of `map`, if they fit and are aligned.
+    pub(crate) fn new(map: Mmap, offset: usize, len: usize) -> Option<Self> {
+        let end = len.checked_mul(size_of::<T>())?.checked_add(offset)?;
+        let aligned = map.as_ptr().wrapping_add(offset).cast::<T>().is_aligned();
+        (end <= map.len() && aligned).then_some(Self { map, offset, len, _marker: PhantomData })
+    }
+}
+
+impl<T: Pod> Deref for Storage<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Storage::Owned(vec) => vec,
+            // SAFETY: `Mapped::new` checked that the items are in bounds and aligned, and
+            // `T: Pod` makes any bytes a valid `T`.
+            Storage::Mapped(m) => unsafe {
+                slice::from_raw_parts(m.map.as_ptr().add(m.offset) as *const T, m.len)
+            },
+        }
+    }
+}
+
+impl<T: Pod> From<Vec<T>> for Storage<T> {
+    fn from(vec: Vec<T>) -> Self {
+        Storage::Owned(vec)
+    }
+}
+
+impl<T: Pod + fmt::Debug> fmt::Debug for Storage<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,266 @@
+use std::hint::select_unpredictable;
+
+use crate::{query::{searchable::{batched, SearchScheme, Searchable}, query_kind::{Kind, QueryKind}}, utils::prefetch_index};
+
+use super::ranked::{answer, Ranked};
+
+// Keys are u32, so a complete tree over all of them is at most 33 levels high.
+const MAX_HEIGHT: usize = 33;
+
+/// Where the nodes of one depth live relative to the recursive block containing them.
+///
+/// A node at depth `d` is the root of a bottom tree of the block whose root sits at
+/// `top_depth`. The bottom trees of that block are stored back to back after its top tree.
+#[derive(Clone, Copy, Debug, Default)]
+struct Level {
+    top_depth: usize,
+    top_size: usize,
+    bottom_size: usize,
+    mask: usize,
+}
+
+/// A complete binary tree in van Emde Boas layout. Keys must be below `u32::MAX`, which pads
+/// the tree and which searches report when there is no answer.
+#[derive(Debug)]
+pub struct VebTree {
+    vals: Vec<u32>,
+    levels: Vec<Level>,
+    len: usize,
+}
+
+impl Searchable for VebTree {
+    fn new(sorted_vals: &[u32]) -> Self {
+        assert!(sorted_vals.last().is_none_or(|&key| key < u32::MAX), "Keys must be below u32::MAX");
+        let height = (sorted_vals.len() + 1).next_power_of_two().trailing_zeros().max(1) as usize;
+        assert!(height <= MAX_HEIGHT, "Too many keys for a van Emde Boas tree");
+
+        fn split(levels: &mut [Level], depth: usize, height: usize) {
+            if height <= 1 {
+                return;
+            }
+            let top = height / 2;
+            let bottom = height - top;
+            levels[depth + top] = Level {
+                top_depth: depth,
+                top_size: (1 << top) - 1,
+                bottom_size: (1 << bottom) - 1,
+                mask: (1 << top) - 1,
+            };
+            split(levels, depth, top);
+            split(levels, depth + top, bottom);
+        }
+
+        let mut levels = vec![Level::default(); height];
+        split(&mut levels, 0, height);
+
+        // Walk the complete tree in BFS order; a node's position only depends on the
+        // position of an ancestor, which has a smaller BFS index.
+        let len = (1 << height) - 1;
+        let mut positions = vec![0; len + 1];
+        let mut vals = vec![u32::MAX; len];
+        for i in 1..=len {
+            let depth = i.ilog2() as usize;
+            let level = levels[depth];
+            let pos = positions[i >> (depth - level.top_depth)]
+                + level.top_size
+                + (i & level.mask) * level.bottom_size;
+            positions[i] = pos;
+
+            let rank = ((2 * (i - (1 << depth)) + 1) << (height - 1 - depth)) - 1;
+            if let Some(&val) = sorted_vals.get(rank) {
+                vals[pos] = val;
+            }
+        }
+
+        Self { vals, levels, len: sorted_vals.len() }
+    }
+
+    fn get_funcs<K: QueryKind>() -> Vec<&'static dyn SearchScheme<Self>> {
+        // The searches only track keys; counting goes through the ranks of `Ranked`.
+        if K::KIND.needs_rank() {
+            return vec!(&Self::search_ranked::<K>);
+        }
+        let batch_128 = Box::leak(Box::new(batched(Self::batch::<128, K>)));
+        let batch_128_prefetch = Box::leak(Box::new(batched(Self::batch_prefetch::<128, K>)));
+        vec!(&Self::search::<K>, &Self::search_prefetch::<K>, batch_128, batch_128_prefetch)
+    }
+}
+
+impl VebTree {
+    fn get(&self, index: usize) -> u32 {
+        debug_assert!(index < self.vals.len(), "index {index} out of bounds");
+        // SAFETY: the tree is complete, so every node position on a root-to-leaf path exists.
+        unsafe { *self.vals.get_unchecked(index) }
+    }
+
+    fn level(&self, depth: usize) -> Level {
+        debug_assert!(depth < self.levels.len(), "depth {depth} out of bounds");
+        // SAFETY: searches only go `self.levels.len()` levels deep.
+        unsafe { *self.levels.get_unchecked(depth) }
+    }
+
+    /// Position of the node with BFS index `idx` at `depth`, given the positions of its ancestors.
+    #[inline(always)]
+    fn pos(&self, positions: &[usize], depth: usize, idx: usize) -> usize {
+        let level = self.level(depth);
+        positions[level.top_depth] + level.top_size + (idx & level.mask) * level.bottom_size
+    }
+
+    /// Keeps the last node that can answer the query: where the search turned left for
+    /// successor queries, or right for predecessor queries.
+    #[inline(always)]
+    fn update<K: QueryKind>(ans: u32, key: u32, right: bool) -> u32 {
+        if K::KIND == Kind::Predecessor {
+            select_unpredictable(right, key, ans)
+        } else {
+            select_unpredictable(right, ans, key)
+        }
+    }
+
+    #[inline(never)]
+    pub fn search<K: QueryKind>(&self, q: u32) -> u32 {
+        let q = K::KIND.target(q);
+        let mut positions = [0; MAX_HEIGHT];
+        let mut idx = 1;
+        let mut ans = u32::MAX;
+        for depth in 0..self.levels.len() {
+            let pos = self.pos(&positions, depth, idx);
+            positions[depth] = pos;
+            let key = self.get(pos);
+            let right = q > key;
+            ans = Self::update::<K>(ans, key, right);
+            idx = 2 * idx + right as usize;
+        }
+        ans
+    }
+
+    #[inline(never)]
+    pub fn search_prefetch<K: QueryKind>(&self, q: u32) -> u32 {
+        let q = K::KIND.target(q);
+        let mut positions = [0; MAX_HEIGHT];
+        let mut idx = 1;
+        let mut ans = u32::MAX;
+        let height = self.levels.len();
+        for depth in 0..height {
+            let pos = self.pos(&positions, depth, idx);
+            positions[depth] = pos;
+            if depth + 1 < height {
+                prefetch_index(&self.vals, self.pos(&positions, depth + 1, 2 * idx));
+                prefetch_index(&self.vals, self.pos(&positions, depth + 1, 2 * idx + 1));
+            }
+            let key = self.get(pos);
+            let right = q > key;
+            ans = Self::update::<K>(ans, key, right);
+            idx = 2 * idx + right as usize;
+        }
+        ans
+    }
+
+    #[inline(never)]
+    fn search_ranked<K: QueryKind>(&self, q: u32) -> u32 {
+        answer::<K, _>(self, q, self.lower_bound(K::KIND.target(q)))
+    }
+
+    #[inline(always)]
+    fn batch_impl<const P: usize, K: QueryKind, const PREFETCH: bool>(&self, values: &[u32; P]) -> [u32; P] {
+        let values = values.map(|v| K::KIND.target(v));
+        let mut positions = [[0; MAX_HEIGHT]; P];
+        let mut idx = [1; P];
+        let mut ans = [u32::MAX; P];
+        let height = self.levels.len();
+        for depth in 0..height {
+            for i in 0..P {
+                let key = self.get(positions[i][depth]);
+                let right = values[i] > key;
+                ans[i] = Self::update::<K>(ans[i], key, right);
+                idx[i] = 2 * idx[i] + right as usize;
+                if depth + 1 < height {
+                    let next = self.pos(&positions[i], depth + 1, idx[i]);
+                    positions[i][depth + 1] = next;
+                    if PREFETCH {
+                        prefetch_index(&self.vals, next);
+                    }
+                }
+            }
+        }
+        ans
+    }
+
+    #[inline(never)]
+    fn batch<const P: usize, K: QueryKind>(&self, values: &[u32; P]) -> [u32; P] {
+        self.batch_impl::<P, K, false>(values)
+    }
+
+    #[inline(never)]
+    fn batch_prefetch<const P: usize, K: QueryKind>(&self, values: &[u32; P]) -> [u32; P] {
+        self.batch_impl::<P, K, true>(values)
+    }
+}
+
+impl Ranked for VebTree {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn key_at(&self, rank: usize) -> u32 {
+        if rank >= self.len {
+            return u32::MAX;
+        }
+        // Invert the in-order rank formula of `new` to get the BFS index.
+        let height = self.levels.len();
+        let t = (rank + 1).trailing_zeros() as usize;
+        let depth = height - 1 - t;
+        let idx = (1 << depth) + ((rank + 1) >> (t + 1));
+
+        let mut positions = [0; MAX_HEIGHT];
+        for d in 0..=depth {
+            positions[d] = self.pos(&positions, d, idx >> (depth - d));
+        }
+        self.get(positions[depth])
+    }
+
+    fn lower_bound(&self, value: u32) -> usize {
+        let height = self.levels.len();
+        let mut positions = [0; MAX_HEIGHT];
+        let mut idx = 1;
+        for depth in 0..height {
+            positions[depth] = self.pos(&positions, depth, idx);
+            idx = 2 * idx + (value > self.get(positions[depth])) as usize;
+        }
+        // The lower bound is the last node the search turned left at.
+        let idx = idx >> (idx.trailing_ones() + 1);
+        if idx == 0 {
+            return self.len;
+        }
+        let depth = idx.ilog2() as usize;
+        let rank = ((2 * (idx - (1 << depth)) + 1) << (height - 1 - depth)) - 1;
+        rank.min(self.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::query_kind::LowerBound;
+
+    use super::*;
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_matches_partition_point() {
+        for len in 0..300 {
+            let vals: Vec<u32> = (0..len).map(|i| 3 * i).collect();
+            let tree = VebTree::new(&vals);
+            let queries: Vec<u32> = (0..3 * len + 2).collect();
+            let expected: Vec<u32> = queries.iter()
+                .map(|&q| vals.get(vals.partition_point(|&x| x < q)).copied().unwrap_or(u32::MAX))
+                .collect();
+
+            for func in VebTree::get_funcs::<LowerBound>() {
+                let mut padded = queries.clone();
+                padded.resize(queries.len().next_multiple_of(128), 0);
+                let got = func.query(&tree, &padded);
+                assert_eq!(got[..queries.len()], expected[..], "len {len}, {}", func.get_name());
+            }
+        }
+    }
+}
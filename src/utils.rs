@@ -1,6 +1,23 @@
-use std::intrinsics::prefetch_read_data;
+/// Hints the CPU to load `s[index]` into cache.
+///
+/// `index` may be out of bounds: the address is only computed, never dereferenced.
+#[cfg(all(feature = "nightly", not(miri)))]
+pub fn prefetch_index<T>(s: &[T], index: usize){
+    let ptr = s.as_ptr().wrapping_add(index);
+    // SAFETY: prefetching has no observable effect and cannot fault.
+    unsafe { std::intrinsics::prefetch_read_data::<_, 3>(ptr) };
+}
 
+/// Hints the CPU to load `s[index]` into cache.
+///
+/// `index` may be out of bounds: the address is only computed, never dereferenced.
+#[cfg(all(not(feature = "nightly"), target_arch = "x86_64", not(miri)))]
 pub fn prefetch_index<T>(s: &[T], index: usize){
-    let ptr = unsafe { s.as_ptr().add(index) as *const u64 };
-    unsafe { prefetch_read_data(ptr, 3) };
+    use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+    let ptr = s.as_ptr().wrapping_add(index) as *const i8;
+    // SAFETY: prefetching has no observable effect and cannot fault.
+    unsafe { _mm_prefetch::<_MM_HINT_T0>(ptr) };
 }
+
+#[cfg(any(miri, all(not(feature = "nightly"), not(target_arch = "x86_64"))))]
+pub fn prefetch_index<T>(_s: &[T], _index: usize){}
@@ -0,0 +1,70 @@
+//! The reference model: answers computed with `partition_point` on the sorted keys
+//! themselves, and checks of every scheme against them.
+//!
+//! Shared by the differential tests and the fuzz target, which includes this file by path.
+
+use binary_search::{
+    searches::ranked::RANGE_WIDTH, EqualRange, Kind, LowerBound, Predecessor, QueryKind, RangeCount, Searchable,
+    UpperBound,
+};
+
+/// The answer to query `q`, with `sentinel` for a key that does not exist.
+pub fn expected<K: QueryKind>(keys: &[u32], q: u32, sentinel: u32) -> u32 {
+    let lower = keys.partition_point(|&x| x < q);
+    let upper = keys.partition_point(|&x| x <= q);
+    let key = |rank: usize| keys.get(rank).copied().unwrap_or(sentinel);
+    match K::KIND {
+        Kind::LowerBound => key(lower),
+        Kind::UpperBound => key(upper),
+        Kind::Predecessor => upper.checked_sub(1).map_or(sentinel, key),
+        Kind::EqualRange => (upper - lower) as u32,
+        Kind::RangeCount => {
+            let hi = q.checked_add(RANGE_WIDTH).map_or(keys.len(), |hi| keys.partition_point(|&x| x < hi));
+            (hi - lower) as u32
+        }
+    }
+}
+
+/// The payload `Searchable::new` stores for the answer to `q`, which is its rank.
+pub fn expected_payload<K: QueryKind>(keys: &[u32], q: u32) -> u32 {
+    let rank = match K::KIND {
+        Kind::LowerBound => keys.partition_point(|&x| x < q),
+        Kind::UpperBound => keys.partition_point(|&x| x <= q),
+        Kind::Predecessor => keys.partition_point(|&x| x <= q).wrapping_sub(1),
+        Kind::EqualRange | Kind::RangeCount => unreachable!("payload schemes only answer key queries"),
+    };
+    if rank < keys.len() { rank as u32 } else { u32::MAX }
+}
+
+/// Runs every scheme of `S` for `K` on all queries at once, and the first few one by one.
+fn check<S: Searchable + 'static, K: QueryKind>(s: &S, queries: &[u32], expected: impl Fn(u32) -> u32) {
+    let expected: Vec<u32> = queries.iter().map(|&q| expected(q)).collect();
+    for func in S::all_funcs::<K>() {
+        let name = func.get_name();
+        let answers = func.query(s, queries);
+        assert_eq!(answers.len(), queries.len(), "{} {name}", K::get_name());
+        for ((&q, answer), expected) in queries.iter().zip(answers).zip(&expected) {
+            assert_eq!(answer, *expected, "{} {name}, query {q}", K::get_name());
+        }
+        for (&q, &expected) in queries.iter().zip(&expected).take(3) {
+            assert_eq!(func.query_one(s, q), expected, "{} {name}, single query {q}", K::get_name());
+        }
+    }
+}
+
+pub fn check_all<S: Searchable + 'static>(keys: &[u32], queries: &[u32], sentinel: u32) {
+    let s = S::new(keys);
+    check::<S, LowerBound>(&s, queries, |q| expected::<LowerBound>(keys, q, sentinel));
+    check::<S, UpperBound>(&s, queries, |q| expected::<UpperBound>(keys, q, sentinel));
+    check::<S, Predecessor>(&s, queries, |q| expected::<Predecessor>(keys, q, sentinel));
+    check::<S, EqualRange>(&s, queries, |q| expected::<EqualRange>(keys, q, sentinel));
+    check::<S, RangeCount>(&s, queries, |q| expected::<RangeCount>(keys, q, sentinel));
+}
+
+pub fn check_payloads<S: Searchable + 'static>(keys: &[u32], queries: &[u32]) {
+    let s = S::new(keys);
+    check::<S, LowerBound>(&s, queries, |q| expected_payload::<LowerBound>(keys, q));
+    check::<S, UpperBound>(&s, queries, |q| expected_payload::<UpperBound>(keys, q));
+    check::<S, Predecessor>(&s, queries, |q| expected_payload::<Predecessor>(keys, q));
+    assert!(S::get_funcs::<EqualRange>().is_empty() && S::get_funcs::<RangeCount>().is_empty());
+}
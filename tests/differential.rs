@@ -0,0 +1,147 @@
+//! Runs every search scheme of every structure on random sorted keys and compares the
+//! answers with `partition_point` on the keys themselves.
+//!
+//! Sizes are drawn with a bias towards the node and layer boundaries, and the number of
+//! queries is arbitrary so the batched schemes also answer a partial last batch. Failing
+//! inputs are shrunk by proptest before they are reported.
+
+mod common;
+
+use binary_search::{
+    searches::s_tree::MAX, BEytzinger, CompressedSTree, DynamicSTree, Eytzinger, PrefixLut, STree, STreeMap,
+    SortedVec, VebTree, WithPayloads,
+};
+use common::{check_all, check_payloads};
+use proptest::{collection::SizeRange, prelude::*, sample::{select, Index}, test_runner::FileFailurePersistence};
+
+#[derive(Debug, Clone)]
+struct Input {
+    keys: Vec<u32>,
+    queries: Vec<u32>,
+}
+
+/// Key counts around one node (16), one layer of nodes (272) and two layers (4912), and
+/// anything up to a few layers. Each range shrinks towards its lower end.
+fn len() -> impl Strategy<Value = SizeRange> {
+    prop_oneof![
+        Just(0..40),
+        Just(250..300),
+        Just(4890..4940),
+        Just(0..6000),
+    ].prop_map(SizeRange::from)
+}
+
+/// A query: a value of its own, or a key of the input plus an offset of -1, 0 or 1.
+#[derive(Debug, Clone)]
+enum Query {
+    Value(u32),
+    Near(Index, i8),
+}
+
+/// Sorted keys up to `top`, and queries in and around their range.
+///
+/// A small key range gives long runs of duplicates, `top` gives gaps too wide for the
+/// compressed leaves. With `top` at `u32::MAX - 1`, up to two more keys are the largest allowed.
+fn input(top: u32) -> impl Strategy<Value = Input> {
+    let max_top_keys: usize = if top == u32::MAX - 1 { 2 } else { 0 };
+    (len(), select(vec![64, 1 << 17, top])).prop_flat_map(move |(len, spread)| {
+        let query = prop_oneof![
+            3 => (any::<Index>(), -1..=1i8).prop_map(|(idx, offset)| Query::Near(idx, offset)),
+            1 => (0..=spread.saturating_add(2)).prop_map(Query::Value),
+            1 => select(vec![0, MAX, MAX + 1, u32::MAX]).prop_map(Query::Value),
+            1 => any::<u32>().prop_map(Query::Value),
+        ];
+        (prop::collection::vec(0..=spread, len), prop::collection::vec(query, 0..400), 0..=max_top_keys)
+    }).prop_map(move |(mut keys, queries, top_keys)| {
+        keys.extend(std::iter::repeat_n(top, top_keys));
+        keys.sort_unstable();
+        let queries = queries.into_iter().map(|query| match query {
+            Query::Value(q) => q,
+            Query::Near(_, offset) if keys.is_empty() => offset.max(0) as u32,
+            Query::Near(idx, offset) => idx.get(&keys).wrapping_add_signed(offset.into()),
+        }).collect();
+        Input { keys, queries }
+    })
+}
+
+/// Building every structure is slow in debug builds, so there are fewer cases than usual but
+/// enough shrink steps to get a failure down to a handful of keys and queries.
+fn config() -> ProptestConfig {
+    ProptestConfig {
+        cases: 48,
+        max_shrink_iters: 4096,
+        // The default looks for the crate root next to the source file, which `tests/` lacks.
+        failure_persistence: Some(Box::new(FileFailurePersistence::WithSource("proptest-regressions"))),
+        ..ProptestConfig::default()
+    }
+}
+
+proptest! {
+    #![proptest_config(config())]
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn sorted_vec(input in input(MAX)) {
+        check_all::<SortedVec>(&input.keys, &input.queries, u32::MAX);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn eytzinger(input in input(MAX)) {
+        check_all::<Eytzinger>(&input.keys, &input.queries, u32::MAX);
+    }
+
+    /// Keys up to `u32::MAX - 1`, for the structures that only reserve their sentinel.
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn full_range(input in input(u32::MAX - 1)) {
+        check_all::<SortedVec>(&input.keys, &input.queries, u32::MAX);
+        check_all::<Eytzinger>(&input.keys, &input.queries, u32::MAX);
+        check_all::<VebTree>(&input.keys, &input.queries, u32::MAX);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn s_tree(input in input(MAX)) {
+        check_all::<STree>(&input.keys, &input.queries, MAX);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn compressed_s_tree(input in input(MAX)) {
+        check_all::<CompressedSTree>(&input.keys, &input.queries, MAX);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn veb_tree(input in input(MAX)) {
+        check_all::<VebTree>(&input.keys, &input.queries, u32::MAX);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn b_eytzinger(input in input(MAX)) {
+        check_all::<BEytzinger>(&input.keys, &input.queries, MAX);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn prefix_lut(input in input(MAX)) {
+        check_all::<PrefixLut<STree, 8>>(&input.keys, &input.queries, MAX);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn dynamic_s_tree(input in input(MAX)) {
+        check_all::<DynamicSTree<64>>(&input.keys, &input.queries, MAX);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn payloads(input in input(MAX)) {
+        check_payloads::<WithPayloads<STree, u32>>(&input.keys, &input.queries);
+        check_payloads::<WithPayloads<Eytzinger, u64>>(&input.keys, &input.queries);
+        check_payloads::<STreeMap<u32>>(&input.keys, &input.queries);
+        check_payloads::<STreeMap<u64>>(&input.keys, &input.queries);
+    }
+}